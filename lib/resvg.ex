@@ -8,12 +8,104 @@ defmodule Resvg do
 
   The supported options are:
 
-    * `:width` - Set the width in pixels.
-    * `:height` - Set the height in pixels.
+    * `:width` - Set the width in pixels, or in a physical unit converted
+    with `:dpi`: `{value, :mm}`, `{value, :cm}`, `{value, :in}` or
+    `{value, :pt}`, Example: `{210, :mm}`.
+    * `:height` - Set the height, like `:width`.
     * `:zoom` - Zoom image by a factor, Example: `2.0`.
-    * `:dpi` - Sets the resolution, default to `96`.
+    * `:fit` - Sets how the image fills the size when both `:width` and
+    `:height` are set. By default the aspect ratio is kept and the image may be
+    smaller than requested. The output is exactly `:width` x `:height` with:
+      * `:contain` - Keep the aspect ratio and letterbox.
+      * `:cover` - Keep the aspect ratio and crop.
+      * `:fill` - Stretch the image.
+    * `:align` - Sets where the image is placed when `:fit` letterbox or crop
+    it, one of `:top_left`, `:top`, `:top_right`, `:left`, `:center`, `:right`,
+    `:bottom_left`, `:bottom`, `:bottom_right`, default to `:center`.
+    * `:max_width`, `:max_height` - Scale the image down, keeping the aspect
+    ratio, only when it is larger. Example: `max_width: 1024` never upscale
+    small images.
+    * `:min_width`, `:min_height` - Scale the image up, keeping the aspect
+    ratio, only when it is smaller. The max options win over the min ones.
+    * `:region` - Renders only the `{x, y, width, height}` area of the svg,
+    in the svg user units, the ones of its `viewBox` when it has one. The size
+    options apply to the region instead of the whole svg,
+    Example: `{100, 0, 50, 50}`.
+    * `:rotate` - Rotates the image clockwise by the given degrees, default to
+    `0`. The output is enlarged to hold the whole rotated image, Example: `90`,
+    `-45`.
+    * `:flip` - Mirrors the image before rotating it, `:horizontal` or
+    `:vertical`.
+    * `:padding` - Adds space around the image, filled with `:background`. It
+    enlarges the output. Accept a length for all sides or a
+    `{top, right, bottom, left}` tuple of lengths. A length is a number of
+    pixels or `{value, :percent}` of the image width for left and right, of
+    its height for top and bottom. Example: `24`, `{10, :percent}`.
+    * `:color_transform` - Transforms the colors of the rendered image,
+    including the background. One of `:grayscale`, `:sepia`, `:invert` or a
+    list of 20 numbers: a 4x5 matrix in rows, like the svg `feColorMatrix`
+    with channels between `0` and `1`. Example:
+    `[1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0.5, 0]` halves
+    the opacity.
+    * `:dpi` - Sets the resolution used for the physical units of the svg and
+    of `:width` and `:height`, default to `96`.
+    It is also written in the png files as their physical resolution.
+    * `:png_metadata` - A list of `{keyword, text}` written as png text
+    chunks. Atom keywords are capitalized, Example:
+    `[title: "Logo", author: "Jane", creation_time: "2024-01-01"]` writes the
+    `Title`, `Author` and `Creation Time` keywords.
+    * `:png` - Sets how the png files are encoded:
+      * `:compression` - `:fast`, `:default` or `:best`, default to `:default`.
+      * `:filter` - The rows filter `:none`, `:sub`, `:up`, `:avg`, `:paeth`
+      or `:adaptive` which picks one per row, default to `:sub`.
+      * `:reduce` - Write the image as indexed, grayscale or RGB when it is
+      lossless and smaller than RGBA, default to `false`.
+    * `:palette` - Quantizes the image to a palette and writes an indexed png,
+    or a gif. Example: `[colors: 16, dither: true]`. The palette options are:
+      * `:colors` - Maximum number of colors, between `2` and `256`, default
+      to `256`.
+      * `:dither` - Spread the quantization error with Floyd–Steinberg
+      dithering, default to `false`.
+      * `:format` - `:png` or `:gif`, default to `:png`. Gif has no partial
+      transparency, pixels are either opaque or fully transparent.
+    * `:monochrome` - Converts the image to 1-bit black and white, transparent
+    pixels are white. It takes precedence over `:palette`. Example:
+    `[format: :zpl, dither: true]`. The monochrome options are:
+      * `:format` - The output format, default to `:png`:
+        * `:png` - A 1-bit grayscale png.
+        * `:pbm` - A binary portable bitmap (`P4`).
+        * `:bits` - Only the rows, 8 pixels per byte with the most significant
+        bit first and `1` for black. Each row is padded to a whole byte.
+        * `:escpos` - An ESC/POS `GS v 0` raster image command.
+        * `:zpl` - A ZPL label with the image as a `^GFA` graphic field.
+      * `:threshold` - Pixels with a luminance below it are black, default to
+      `128`.
+      * `:dither` - Use Floyd–Steinberg dithering instead of a plain
+      threshold, default to `false`.
     * `:background` - Sets the background color, accept CSS3 color
     Example: `red`, `#fff`, `#fff000`.
+    * `:stylesheet` - A CSS string applied while parsing the svg, its rules
+    override presentation attributes but the svg own `<style>` rules take
+    precedence. Example: `".primary { fill: #f00 }"`.
+    * `:current_color` - Sets the color `currentColor` resolves to when the
+    svg doesn't set one, default to black. Example: `"#1d4ed8"`.
+    * `:variables` - A map of values replacing `var(--name)` and
+    `var(--name, fallback)` in attributes and stylesheets of the svg. Unknown
+    names use their fallback. Example: `%{primary: "#f00"}`.
+    * `:hide_ids` - A list of element ids removed with their children before
+    rendering. Example: `["grid", "watermark"]`.
+    * `:show_only_ids` - A list of element ids to render, other shapes,
+    groups and texts are removed unless they contain one of them. Resources
+    like gradients and `defs` are kept. Default to `nil`, rendering everything.
+    * `:text` - A map of element ids to the new content of `text`, `tspan` or
+    `textPath` elements, child `tspan` elements are replaced too. The text is
+    laid out as usual, no XML escaping is needed.
+    Example: `%{"name" => "Ada Lovelace"}`.
+    * `:patch` - A list of `{selector_or_id, attribute, value}` changes applied
+    in order to the svg before rendering. The target is an element id or else
+    a CSS selector, a `nil` value removes the attribute and atom attributes use
+    dashes, `:stroke_width` is `stroke-width`.
+    Example: `[{"bar-1", :height, 42}, {".bars rect", :fill, "#f00"}]`.
     * `:languages` - Sets a list of languages that will be used during the
     `systemLanguage` attribute resolving, Example: `["en-US", "fr-FR"]`, default
     to `["en"]`.
@@ -24,8 +116,35 @@ defmodule Resvg do
     * `:image_rendering` - Selects the default image rendering method
     default to `:optimize_quality`.
     * `:resources_dir` - Sets a directory that will be used during relative
-    paths resolving. This field is mandatory for all functions
-    except `svg_to_png/3` because it default to the `svg_input` path.
+    paths resolving. It default to the `svg_input` directory for
+    `svg_to_png/3` and `query_all/2`. Other functions return an error when the
+    svg references a relative path and this field is not set.
+    * `:images` - A map of `href` to image binary (PNG, JPEG, GIF, WebP or
+    SVG). Images referenced by one of these `href` are taken from the map
+    instead of being read from `resources_dir`, Example:
+    `%{"avatar.png" => File.read!("avatar.png")}`.
+    * `:limits` - Sets safety limits for untrusted input. When a limit is
+    exceeded the functions return `{:error, {:limit_exceeded, limit}}`.
+    Unset limits are unlimited. The supported limits are:
+      * `:max_input_bytes` - Maximum size of the svg source, a svgz file is
+      checked before and while it is inflated.
+      * `:max_elements` - Maximum number of elements, checked on the svg
+      source and once `use` elements are instantiated. Text and comments are
+      not counted.
+      * `:max_pixels` - Maximum output `width * height`.
+      * `:max_filter_area` - Maximum area of a filter region in output pixels.
+      * `:allow_dtd` - Allow DTD in the svg source, default to `true`.
+
+    Nested svg images, from `data:` urls, files or `:images`, are checked
+    against `:max_input_bytes`, `:max_elements` and `:allow_dtd` too.
+    * `:timeout` - Sets the maximum time in milliseconds to wait for the
+    result. When it elapses the functions return `{:error, :timeout}` and the
+    scheduler is released, the render is abandoned in the background and stops
+    at its next band of rows. Svgs with filters are rendered in one go, as the
+    filters may reach over bands, the other ones by bands when a timeout is
+    set: edges crossing bands may be anti-aliased slightly differently. While
+    16 abandoned renders are still running, the functions fail with an error
+    instead of starting new ones. Default to `nil` which waits forever.
     * `:font_family` - Sets the default font family that will be used when
     no `font-family` is present, default to `Times New Roman`.
     * `:font_size` - Sets the default font size that will be used when no
@@ -51,6 +170,13 @@ defmodule Resvg do
   alias Resvg.Options
 
   @type png_buffer :: [0..255]
+  @type icon_buffer :: [0..255]
+  @type grid_tile :: {x :: non_neg_integer(), y :: non_neg_integer(), png_buffer}
+  @type xyz_tile ::
+          {z :: non_neg_integer(), x :: non_neg_integer(), y :: non_neg_integer(), png_buffer}
+  @type tiles_option :: {:layout, :grid | :xyz} | {:output_dir, Path.t()}
+
+  @type error_reason :: String.t() | {:limit_exceeded, Options.limit()} | :timeout
 
   @doc """
   Try to convert the contents of `in_svg` to `out_png`.
@@ -74,7 +200,7 @@ defmodule Resvg do
           in_svg :: Path.t(),
           out_png :: Path.t(),
           options :: Options.resvg_options()
-        ) :: :ok | {:error, String.t()}
+        ) :: :ok | {:error, error_reason}
   def svg_to_png(in_svg, out_png, opts \\ []) do
     options = struct(Options, opts)
     Resvg.Native.svg_to_png(in_svg, out_png, options)
@@ -85,8 +211,8 @@ defmodule Resvg do
 
   `svg_string` must be a valid svg file.
   `out_png` must be a path to a non-existent file.
-  `opts` refer to [options](#module-common-options) must at least set the
-  `resources_dir` key to a valid path.
+  `opts` refer to [options](#module-common-options), the `resources_dir` key
+  must be set when the svg references relative paths.
 
   The functions return `:ok` in case of success. Otherise, it returns
   `{:error, reason}` if an error occurs.
@@ -97,15 +223,15 @@ defmodule Resvg do
         <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
           <rect width="100" height="100" />
         </svg>"
-      Resvg.svg_string_to_png(svg_string, "output.png", resources_dir: "/tmp")
+      Resvg.svg_string_to_png(svg_string, "output.png")
       :ok
   """
   @spec svg_string_to_png(
           svg_string :: String.t(),
           out_png :: Path.t(),
           options :: Options.resvg_options()
-        ) :: :ok | {:error, String.t()}
-  def svg_string_to_png(svg_string, out_png, opts) do
+        ) :: :ok | {:error, error_reason}
+  def svg_string_to_png(svg_string, out_png, opts \\ []) do
     options = struct(Options, opts)
     Resvg.Native.svg_string_to_png(svg_string, out_png, options)
   end
@@ -114,8 +240,8 @@ defmodule Resvg do
   Try to convert `svg_string` to a png buffer..
 
   `svg_string` must be a valid svg file.
-  `opts` refer to [options](#module-common-options) must at least set the
-  `resources_dir` key to a valid path.
+  `opts` refer to [options](#module-common-options), the `resources_dir` key
+  must be set when the svg references relative paths.
 
   The functions return `{:ok, buffer}` in case of success. Otherise, it returns
   `{:error, reason}` if an error occurs.
@@ -126,35 +252,268 @@ defmodule Resvg do
         <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
           <rect width="100" height="100" />
         </svg>"
-      Resvg.svg_string_to_png(svg_string, "output.png", resources_dir: "/tmp")
+      Resvg.svg_string_to_png_buffer(svg_string)
       {:ok, buffer}
   """
   @spec svg_string_to_png_buffer(
           svg_string :: String.t(),
           options :: Options.resvg_options()
-        ) :: {:ok, png_buffer} | {:error, String.t()}
-  def svg_string_to_png_buffer(svg_string, opts) do
+        ) :: {:ok, png_buffer} | {:error, error_reason}
+  def svg_string_to_png_buffer(svg_string, opts \\ []) do
     options = struct(Options, opts)
     Resvg.Native.svg_string_to_png_buffer(svg_string, options)
   end
 
+  @doc ~S"""
+  Convert `svg_string` to a png buffer on a background thread pool.
+
+  `svg_string` must be a valid svg file.
+  `opts` refer to [options](#module-common-options), the `:timeout` option is
+  not applied, use `receive ... after` instead.
+  `pid` is the process receiving the result, default to the caller.
+
+  The function returns a reference right away. Once the render is done
+  `{ref, {:ok, buffer}}` or `{ref, {:error, reason}}` is sent to `pid`.
+
+  ## Examples
+
+      svg_string = "
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>"
+      ref = Resvg.render_async(svg_string)
+
+      receive do
+        {^ref, {:ok, buffer}} -> buffer
+      end
+  """
+  @spec render_async(
+          svg_string :: String.t(),
+          options :: Options.resvg_options(),
+          pid :: pid()
+        ) :: reference()
+  def render_async(svg_string, opts \\ [], pid \\ self()) do
+    options = struct(Options, opts)
+    ref = make_ref()
+    :ok = Resvg.Native.render_async(svg_string, options, pid, ref)
+    ref
+  end
+
+  @doc ~S"""
+  Convert `svg_string` to one png buffer per size, the svg is parsed once.
+
+  `svg_string` must be a valid svg file.
+  `sizes` is a list of sizes, a size is either:
+
+    * a width in pixels, Example: `64`.
+    * a `{width, height}` tuple in pixels, following the `:fit` option.
+    * `{:width, width}`, `{:height, height}` or `{:zoom, factor}`.
+
+  `opts` refer to [options](#module-common-options), the `:width`, `:height`
+  and `:zoom` options are ignored.
+
+  The functions return `{:ok, buffers}` with the buffers in the same order
+  as `sizes`. Otherise, it returns `{:error, reason}` if an error occurs.
+
+  ## Examples
+
+      svg_string = "
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>"
+      Resvg.render_sizes(svg_string, [16, 32, {:height, 64}])
+      {:ok, [buffer16, buffer32, buffer64]}
+  """
+  @spec render_sizes(
+          svg_string :: String.t(),
+          sizes :: [Options.size()],
+          options :: Options.resvg_options()
+        ) :: {:ok, [png_buffer]} | {:error, error_reason}
+  def render_sizes(svg_string, sizes, opts \\ []) do
+    options = struct(Options, opts)
+    Resvg.Native.render_sizes(svg_string, sizes, options)
+  end
+
+  @doc ~S"""
+  Convert `svg_string` to an `.ico` buffer holding one png per size.
+
+  `svg_string` must be a valid svg file.
+  `sizes` is a list of sizes in pixels between `1` and `256`, default to
+  `[16, 32, 48]`. Icons are square, `:fit` default to `:contain`.
+  `opts` refer to [options](#module-common-options), the `:width`, `:height`,
+  `:zoom`, `:max_*` and `:min_*` options are ignored. The padding and the
+  rotated image are scaled down to fit in the square. The images are always
+  png, `:palette` and `:monochrome` write indexed and 1-bit png.
+
+  The functions return `{:ok, buffer}` in case of success. Otherise, it
+  returns `{:error, reason}` if an error occurs.
+
+  ## Examples
+
+      svg_string = "
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>"
+      {:ok, buffer} = Resvg.render_ico(svg_string, [16, 32, 48])
+      File.write!("favicon.ico", buffer)
+  """
+  @spec render_ico(
+          svg_string :: String.t(),
+          sizes :: [1..256],
+          options :: Options.resvg_options()
+        ) :: {:ok, icon_buffer} | {:error, error_reason}
+  def render_ico(svg_string, sizes \\ [16, 32, 48], opts \\ []) do
+    options = struct(Options, opts)
+    Resvg.Native.render_icon(svg_string, :ico, sizes, options)
+  end
+
+  @doc ~S"""
+  Convert `svg_string` to a macOS `.icns` buffer holding one png per size.
+
+  `svg_string` must be a valid svg file.
+  `sizes` is a list of sizes in pixels among `16`, `32`, `64`, `128`, `256`,
+  `512` and `1024`, default to `[16, 32, 128, 256, 512]`. Icons are square,
+  `:fit` default to `:contain`.
+  `opts` refer to [options](#module-common-options), the `:width`, `:height`,
+  `:zoom`, `:max_*` and `:min_*` options are ignored. The padding and the
+  rotated image are scaled down to fit in the square. The images are always
+  png, `:palette` and `:monochrome` write indexed and 1-bit png.
+
+  The functions return `{:ok, buffer}` in case of success. Otherise, it
+  returns `{:error, reason}` if an error occurs.
+
+  ## Examples
+
+      svg_string = "
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>"
+      {:ok, buffer} = Resvg.render_icns(svg_string)
+      File.write!("icon.icns", buffer)
+  """
+  @spec render_icns(
+          svg_string :: String.t(),
+          sizes :: [16 | 32 | 64 | 128 | 256 | 512 | 1024],
+          options :: Options.resvg_options()
+        ) :: {:ok, icon_buffer} | {:error, error_reason}
+  def render_icns(svg_string, sizes \\ [16, 32, 128, 256, 512], opts \\ []) do
+    options = struct(Options, opts)
+    Resvg.Native.render_icon(svg_string, :icns, sizes, options)
+  end
+
+  @doc ~S"""
+  Convert many svg strings to png buffers in parallel.
+
+  `items` is a list of `{svg_string, opts}`.
+  `batch_opts` refer to [options](#module-common-options), they are the
+  defaults of every item `opts`. The fonts are loaded once from `batch_opts`
+  and shared by all the items, font options set on an item are ignored. The
+  `:timeout` option of `batch_opts` applies to the whole batch.
+
+  The functions return `{:ok, results}` with one `{:ok, buffer}` or
+  `{:error, reason}` per item, in the same order as `items`. Otherise, it
+  returns `{:error, reason}` if the whole batch failed.
+
+  ## Examples
+
+      svg_string = "
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>"
+      Resvg.render_batch([{svg_string, width: 16}, {svg_string, width: 32}], background: "white")
+      {:ok, [{:ok, buffer16}, {:ok, buffer32}]}
+  """
+  @spec render_batch(
+          items :: [{String.t(), Options.resvg_options()}],
+          batch_options :: Options.resvg_options()
+        ) :: {:ok, [{:ok, png_buffer} | {:error, error_reason}]} | {:error, error_reason}
+  def render_batch(items, batch_opts \\ []) do
+    batch_options = struct(Options, batch_opts)
+
+    items =
+      Enum.map(items, fn {svg_string, opts} ->
+        {svg_string, struct(Options, Keyword.merge(batch_opts, opts))}
+      end)
+
+    Resvg.Native.render_batch(items, batch_options)
+  end
+
+  @doc ~S"""
+  Convert `svg_string` to square png tiles of `tile_size` pixels.
+
+  The full size render is never allocated, which allows outputs too large
+  for a single image.
+
+  `svg_string` must be a valid svg file.
+  `tile_size` is the tile width and height in pixels.
+  `opts` refer to [options](#module-common-options), the size options set
+  the full size render. The tiles options are:
+
+    * `:layout` - How the render is split, default to `:grid`:
+      * `:grid` - Tiles of the full size render, the last column and row are
+      cropped to the render size.
+      * `:xyz` - A zoom pyramid as used by web maps. The last zoom level is the
+      full size render, each previous level halves it down to a single tile at
+      zoom `0`. Tiles are always `tile_size` square.
+    * `:output_dir` - Write the tiles to this directory instead of returning
+    them, `x_y.png` for `:grid` and `z/x/y.png` for `:xyz`. The extension
+    follows the output format: `gif` for a `:gif` palette, `pbm` and `zpl` for
+    those monochrome formats and `bin` for `:bits` and `:escpos`.
+
+  The filters reaching over a tile are rendered on a larger image, then
+  cropped to the tile, so the tiles put together match a single render.
+
+  The functions return `{:ok, tiles}` with `{x, y, buffer}` tiles for
+  `:grid` and `{z, x, y, buffer}` tiles for `:xyz`, or `:ok` when
+  `:output_dir` is set. Otherise, it returns `{:error, reason}` if an error
+  occurs.
+
+  ## Examples
+
+      svg_string = "
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>"
+      Resvg.render_tiles(svg_string, 256, width: 1024)
+      {:ok, [{0, 0, buffer}, {0, 1, buffer}, ...]}
+
+      Resvg.render_tiles(svg_string, 256, zoom: 500, layout: :xyz, output_dir: "tiles")
+      :ok
+  """
+  @spec render_tiles(
+          svg_string :: String.t(),
+          tile_size :: pos_integer(),
+          options :: [tiles_option | Options.resvg_option()]
+        ) :: :ok | {:ok, [grid_tile]} | {:ok, [xyz_tile]} | {:error, error_reason}
+  def render_tiles(svg_string, tile_size, opts \\ []) do
+    {tiles_opts, opts} = Keyword.split(opts, [:layout, :output_dir])
+    options = struct(Options, opts)
+
+    Resvg.Native.render_tiles(
+      svg_string,
+      tile_size,
+      Keyword.get(tiles_opts, :layout, :grid),
+      Keyword.get(tiles_opts, :output_dir),
+      options
+    )
+  end
+
   @doc """
   List successfully loaded font faces. Useful for debugging.
 
-  `opts` refer to [options](#module-common-options) must at least set the
-  `resources_dir` key to a valid path.
+  `opts` refer to [options](#module-common-options).
 
   The functions return `{:ok, fonts_list}` in case of success. Otherise, it returns
   `{:error, reason}` if an error occurs.
 
   ## Examples
 
-      Resvg.list_fonts(resources_dir: "/tmp")
+      Resvg.list_fonts()
       {:ok, ["/usr/share/fonts/truetype/dejavu/DejaVuSansMono-BoldOblique.ttf..", ...]}
   """
   @spec list_fonts(options :: Options.resvg_options()) ::
           {:ok, [String.t()]} | {:error, String.t()}
-  def list_fonts(opts) do
+  def list_fonts(opts \\ []) do
     options = struct(Options, opts)
     Resvg.Native.list_fonts(options)
   end
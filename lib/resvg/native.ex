@@ -33,6 +33,11 @@ defmodule Resvg.Native do
   def svg_to_png(_in_svg, _out_png, _options), do: error()
   def svg_string_to_png(_svg_string, _png_path, _options), do: error()
   def svg_string_to_png_buffer(_svg_string, _options), do: error()
+  def render_async(_svg_string, _options, _pid, _ref), do: error()
+  def render_sizes(_svg_string, _sizes, _options), do: error()
+  def render_icon(_svg_string, _format, _sizes, _options), do: error()
+  def render_batch(_items, _batch_options), do: error()
+  def render_tiles(_svg_string, _tile_size, _layout, _output_dir, _options), do: error()
   def list_fonts(_options), do: error()
   def query_all(_in_svg, _options), do: error()
 
@@ -11,17 +11,104 @@ defmodule Resvg.Options do
   @type text_rendering :: :optimize_speed | :optimize_legibility | :geometric_precision
   @type image_rendering :: :optimize_quality | :optimize_speed
 
-  @type resvg_options :: [
-          {:width, non_neg_integer()}
+  @type dimension :: non_neg_integer() | {number(), :mm | :cm | :in | :pt}
+
+  @type fit :: :contain | :cover | :fill
+
+  @type align ::
+          :top_left
+          | :top
+          | :top_right
+          | :left
+          | :center
+          | :right
+          | :bottom_left
+          | :bottom
+          | :bottom_right
+
+  @type size ::
+          non_neg_integer()
+          | {non_neg_integer(), non_neg_integer()}
+          | {:width, non_neg_integer()}
           | {:height, non_neg_integer()}
           | {:zoom, float()}
+
+  @type region :: {number(), number(), number(), number()}
+
+  @type color_transform :: :grayscale | :sepia | :invert | [number()]
+
+  @type flip :: :horizontal | :vertical
+
+  @type length :: number() | {number(), :percent}
+
+  @type padding :: length() | {length(), length(), length(), length()}
+
+  @type limit :: :max_input_bytes | :max_elements | :max_pixels | :max_filter_area | :allow_dtd
+
+  @type limits :: [
+          {:max_input_bytes, non_neg_integer() | nil}
+          | {:max_elements, non_neg_integer() | nil}
+          | {:max_pixels, non_neg_integer() | nil}
+          | {:max_filter_area, non_neg_integer() | nil}
+          | {:allow_dtd, boolean()}
+        ]
+
+  @type png :: [
+          {:compression, :fast | :default | :best}
+          | {:filter, :none | :sub | :up | :avg | :paeth | :adaptive}
+          | {:reduce, boolean()}
+        ]
+
+  @type palette :: [
+          {:colors, 2..256}
+          | {:dither, boolean()}
+          | {:format, :png | :gif}
+        ]
+
+  @type monochrome :: [
+          {:format, :png | :pbm | :bits | :escpos | :zpl}
+          | {:threshold, 0..255}
+          | {:dither, boolean()}
+        ]
+
+  @type patch :: {String.t(), atom() | String.t(), String.t() | number() | nil}
+
+  @type resvg_option ::
+          {:width, dimension()}
+          | {:height, dimension()}
+          | {:zoom, float()}
+          | {:fit, fit() | nil}
+          | {:align, align()}
+          | {:max_width, pos_integer() | nil}
+          | {:max_height, pos_integer() | nil}
+          | {:min_width, pos_integer() | nil}
+          | {:min_height, pos_integer() | nil}
+          | {:region, region() | nil}
+          | {:rotate, number()}
+          | {:flip, flip() | nil}
+          | {:padding, padding() | nil}
+          | {:color_transform, color_transform() | nil}
           | {:dpi, 10..4000}
+          | {:png_metadata, [{atom() | String.t(), String.t()}]}
+          | {:png, png()}
+          | {:palette, palette() | nil}
+          | {:monochrome, monochrome() | nil}
           | {:background, String.t()}
+          | {:stylesheet, String.t() | nil}
+          | {:current_color, String.t() | nil}
+          | {:variables, %{(atom() | String.t()) => String.t()}}
+          | {:hide_ids, [String.t()]}
+          | {:show_only_ids, [String.t()] | nil}
+          | {:text, %{(atom() | String.t()) => String.t()}}
+          | {:patch, [patch()]}
           | {:languages, [String.t()]}
           | {:shape_rendering, shape_rendering()}
           | {:text_rendering, text_rendering()}
           | {:image_rendering, image_rendering()}
           | {:resources_dir, Path.t()}
+          | {:images, %{String.t() => binary()}}
+          | {:limits, limits()}
+          | {:timeout, pos_integer() | nil}
           | {:font_family, String.t()}
           | {:font_size, non_neg_integer()}
           | {:serif_family, String.t()}
@@ -32,18 +119,44 @@ defmodule Resvg.Options do
           | {:font_files, [Path.t()]}
           | {:font_dirs, [Path.t()]}
           | {:skip_system_fonts, boolean()}
-        ]
+
+  @type resvg_options :: [resvg_option()]
 
   defstruct width: nil,
             height: nil,
             zoom: nil,
+            fit: nil,
+            align: :center,
+            max_width: nil,
+            max_height: nil,
+            min_width: nil,
+            min_height: nil,
+            region: nil,
+            rotate: 0,
+            flip: nil,
+            padding: nil,
+            color_transform: nil,
             dpi: 96,
+            png_metadata: [],
+            png: [],
+            palette: nil,
+            monochrome: nil,
             background: nil,
+            stylesheet: nil,
+            current_color: nil,
+            variables: %{},
+            hide_ids: [],
+            show_only_ids: nil,
+            text: %{},
+            patch: [],
             languages: ["en"],
             shape_rendering: :geometric_precision,
             text_rendering: :optimize_legibility,
             image_rendering: :optimize_quality,
             resources_dir: nil,
+            images: %{},
+            limits: [],
+            timeout: nil,
             font_family: nil,
             font_size: 12,
             serif_family: nil,
@@ -0,0 +1,314 @@
+// Mutable copy of a parsed svg, changed before usvg conversion and written
+// back to XML.
+//
+// Only what usvg reads is kept: elements of the SVG namespace, text, and
+// attributes without a namespace or in the xlink and xml ones. Entities
+// are already expanded by roxmltree, so the output never needs a DTD.
+
+use std::fmt::Write;
+use usvg::roxmltree;
+
+pub const SVG_NS: &str = "http://www.w3.org/2000/svg";
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+
+#[derive(Clone, Debug)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Element {
+    /// Local name, the element is always in the SVG namespace.
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Namespace {
+    None,
+    Xlink,
+    Xml,
+}
+
+#[derive(Clone, Debug)]
+pub struct Attribute {
+    pub namespace: Namespace,
+    pub name: String,
+    pub value: String,
+}
+
+impl Element {
+    pub fn from_xmltree(document: &roxmltree::Document) -> Self {
+        from_node(document.root_element())
+    }
+
+    /// Value of the attribute `name`, which may be prefixed by `xlink:` or
+    /// `xml:`.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        let (namespace, name) = split_name(name);
+        self.attributes
+            .iter()
+            .find(|a| a.namespace == namespace && a.name == name)
+            .map(|a| a.value.as_str())
+    }
+
+    /// Replaces the attribute value or adds it.
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        let (namespace, name) = split_name(name);
+        match self
+            .attributes
+            .iter_mut()
+            .find(|a| a.namespace == namespace && a.name == name)
+        {
+            Some(attribute) => attribute.value = value.to_string(),
+            None => self.attributes.push(Attribute {
+                namespace,
+                name: name.to_string(),
+                value: value.to_string(),
+            }),
+        }
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) {
+        let (namespace, name) = split_name(name);
+        self.attributes
+            .retain(|a| !(a.namespace == namespace && a.name == name));
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.attribute("id")
+    }
+
+    /// Calls `f` on this element and all the descendant ones, parents first.
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Element)) {
+        f(self);
+        for child in &mut self.children {
+            if let Node::Element(element) = child {
+                element.visit_mut(f);
+            }
+        }
+    }
+
+    /// Paths of the elements with the id `target` or, when there is none,
+    /// matching `target` as a CSS selector list.
+    pub fn select(&self, target: &str) -> Result<Vec<Path>, String> {
+        let by_id = self.find_all(&|element| element.element().id() == Some(target));
+        if !by_id.is_empty() {
+            return Ok(by_id);
+        }
+
+        let selectors = target
+            .split(',')
+            .map(|text| {
+                simplecss::Selector::parse(text.trim())
+                    .ok_or_else(|| format!("invalid selector '{}'", target))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self.find_all(&|element| selectors.iter().any(|s| s.matches(element))))
+    }
+
+    fn find_all(&self, predicate: &impl Fn(&Located) -> bool) -> Vec<Path> {
+        let mut paths = Vec::new();
+        let mut stack = vec![Vec::new()];
+        while let Some(path) = stack.pop() {
+            let located = Located { root: self, path };
+            let element = located.element();
+            for (i, child) in element.children.iter().enumerate().rev() {
+                if let Node::Element(_) = child {
+                    let mut child_path = located.path.clone();
+                    child_path.push(i);
+                    stack.push(child_path);
+                }
+            }
+
+            if predicate(&located) {
+                paths.push(located.path);
+            }
+        }
+        paths
+    }
+
+    pub fn descendant_mut(&mut self, path: &[usize]) -> &mut Element {
+        path.iter()
+            .fold(self, |element, i| match &mut element.children[*i] {
+                Node::Element(child) => child,
+                Node::Text(_) => unreachable!("paths only lead to elements"),
+            })
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        self.write(&mut xml, true);
+        xml
+    }
+
+    fn write(&self, xml: &mut String, root: bool) {
+        xml.push('<');
+        xml.push_str(&self.name);
+        if root {
+            let _ = write!(xml, " xmlns=\"{}\" xmlns:xlink=\"{}\"", SVG_NS, XLINK_NS);
+        }
+
+        for attribute in &self.attributes {
+            xml.push(' ');
+            match attribute.namespace {
+                Namespace::None => {}
+                Namespace::Xlink => xml.push_str("xlink:"),
+                Namespace::Xml => xml.push_str("xml:"),
+            }
+            xml.push_str(&attribute.name);
+            xml.push_str("=\"");
+            escape(xml, &attribute.value, true);
+            xml.push('"');
+        }
+
+        if self.children.is_empty() {
+            xml.push_str("/>");
+            return;
+        }
+
+        xml.push('>');
+        for child in &self.children {
+            match child {
+                Node::Element(element) => element.write(xml, false),
+                Node::Text(text) => escape(xml, text, false),
+            }
+        }
+        xml.push_str("</");
+        xml.push_str(&self.name);
+        xml.push('>');
+    }
+}
+
+/// Child indices leading from the root to an element.
+pub type Path = Vec<usize>;
+
+/// An element with the way to its ancestors, for selector matching.
+#[derive(Clone)]
+struct Located<'a> {
+    root: &'a Element,
+    path: Path,
+}
+
+impl<'a> Located<'a> {
+    fn element(&self) -> &'a Element {
+        self.path
+            .iter()
+            .fold(self.root, |element, i| match &element.children[*i] {
+                Node::Element(child) => child,
+                Node::Text(_) => unreachable!("paths only lead to elements"),
+            })
+    }
+}
+
+impl simplecss::Element for Located<'_> {
+    fn parent_element(&self) -> Option<Self> {
+        let mut path = self.path.clone();
+        path.pop()?;
+        Some(Located {
+            root: self.root,
+            path,
+        })
+    }
+
+    fn prev_sibling_element(&self) -> Option<Self> {
+        let parent = self.parent_element()?;
+        let index = *self.path.last()?;
+        let sibling = parent.element().children[..index]
+            .iter()
+            .rposition(|child| matches!(child, Node::Element(_)))?;
+
+        let mut path = parent.path;
+        path.push(sibling);
+        Some(Located {
+            root: self.root,
+            path,
+        })
+    }
+
+    fn has_local_name(&self, name: &str) -> bool {
+        self.element().name == name
+    }
+
+    fn attribute_matches(&self, local_name: &str, operator: simplecss::AttributeOperator) -> bool {
+        self.element()
+            .attribute(local_name)
+            .is_some_and(|value| operator.matches(value))
+    }
+
+    fn pseudo_class_matches(&self, class: simplecss::PseudoClass) -> bool {
+        match class {
+            simplecss::PseudoClass::FirstChild => self.prev_sibling_element().is_none(),
+            _ => false,
+        }
+    }
+}
+
+fn split_name(name: &str) -> (Namespace, &str) {
+    if let Some(name) = name.strip_prefix("xlink:") {
+        (Namespace::Xlink, name)
+    } else if let Some(name) = name.strip_prefix("xml:") {
+        (Namespace::Xml, name)
+    } else {
+        (Namespace::None, name)
+    }
+}
+
+fn from_node(node: roxmltree::Node) -> Element {
+    let attributes = node
+        .attributes()
+        .filter_map(|a| {
+            let namespace = match a.namespace() {
+                None | Some(SVG_NS) => Namespace::None,
+                Some(XLINK_NS) => Namespace::Xlink,
+                Some(XML_NS) => Namespace::Xml,
+                Some(_) => return None,
+            };
+
+            Some(Attribute {
+                namespace,
+                name: a.name().to_string(),
+                value: a.value().to_string(),
+            })
+        })
+        .collect();
+
+    let children = node
+        .children()
+        .filter_map(|child| {
+            if child.is_text() {
+                child.text().map(|text| Node::Text(text.to_string()))
+            } else if child.tag_name().namespace() == Some(SVG_NS) {
+                Some(Node::Element(from_node(child)))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    Element {
+        name: node.tag_name().name().to_string(),
+        attributes,
+        children,
+    }
+}
+
+fn escape(xml: &mut String, text: &str, attribute: bool) {
+    for c in text.chars() {
+        match c {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' if attribute => xml.push_str("&quot;"),
+            // Kept as is instead of being normalized to spaces.
+            '\t' if attribute => xml.push_str("&#9;"),
+            '\n' if attribute => xml.push_str("&#10;"),
+            '\r' => xml.push_str("&#13;"),
+            c => xml.push(c),
+        }
+    }
+}
@@ -0,0 +1,461 @@
+// Png and gif encoding of the rendered pixmaps.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::path;
+
+use crate::monochrome::{self, Monochrome, MonochromeFormat};
+use crate::quantize;
+
+/// How the rendered images are written.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EncodeOptions {
+    /// Physical resolution written in the png `pHYs` chunk.
+    pub dpi: u32,
+    /// Png text chunks as keyword and text.
+    pub metadata: Vec<(String, String)>,
+    pub encoding: Encoding,
+    pub palette: Option<Palette>,
+    pub monochrome: Option<Monochrome>,
+}
+
+impl EncodeOptions {
+    /// File extension of the encoded images, `bin` for the raw printer data.
+    pub fn extension(&self) -> &'static str {
+        match (self.monochrome, self.palette) {
+            (Some(mono), _) => match mono.format {
+                MonochromeFormat::Png => "png",
+                MonochromeFormat::Pbm => "pbm",
+                MonochromeFormat::Bits | MonochromeFormat::EscPos => "bin",
+                MonochromeFormat::Zpl => "zpl",
+            },
+            (None, Some(palette)) if palette.format == PaletteFormat::Gif => "gif",
+            _ => "png",
+        }
+    }
+
+    /// Keeps the palette and monochrome conversions but writes them as png,
+    /// for containers like icons which only hold png images.
+    pub fn force_png(&mut self) {
+        if let Some(ref mut palette) = self.palette {
+            palette.format = PaletteFormat::Png;
+        }
+        if let Some(ref mut mono) = self.monochrome {
+            mono.format = MonochromeFormat::Png;
+        }
+    }
+}
+
+/// Quantizes the image to a palette of `colors`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Palette {
+    pub colors: u32,
+    pub dither: bool,
+    pub format: PaletteFormat,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            colors: 256,
+            dither: false,
+            format: PaletteFormat::Png,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PaletteFormat {
+    Png,
+    /// Gif has a single fully transparent color, alpha is thresholded.
+    Gif,
+}
+
+/// Deflate effort.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Compression {
+    Fast,
+    Default,
+    Best,
+}
+
+/// Row filter, `Adaptive` picks the best filter for each row.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    Adaptive,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Encoding {
+    pub compression: Compression,
+    pub filter: Filter,
+    /// Write the smallest color type holding the image without loss instead
+    /// of always RGBA.
+    pub reduce: bool,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self {
+            compression: Compression::Default,
+            filter: Filter::Sub,
+            reduce: false,
+        }
+    }
+}
+
+/// Image data ready to be written.
+struct Image {
+    color: png::ColorType,
+    depth: png::BitDepth,
+    data: Vec<u8>,
+    palette: Option<Vec<u8>>,
+    trns: Option<Vec<u8>>,
+}
+
+/// Encodes `pixmap` as a 8-bit RGBA png, as a 1-bit image when
+/// `EncodeOptions::monochrome` is set, or as an indexed png or gif when
+/// `EncodeOptions::palette` is set.
+pub fn encode(pixmap: &tiny_skia::Pixmap, options: &EncodeOptions) -> Result<Vec<u8>, String> {
+    // Png and gif store straight alpha, tiny-skia premultiplied alpha.
+    let mut rgba = Vec::with_capacity(pixmap.data().len());
+    for pixel in pixmap.pixels() {
+        let c = pixel.demultiply();
+        rgba.extend_from_slice(&[c.red(), c.green(), c.blue(), c.alpha()]);
+    }
+
+    let (width, height) = (pixmap.width(), pixmap.height());
+
+    match (options.monochrome, options.palette) {
+        (Some(mono), _) => encode_monochrome(&rgba, width, height, mono, options),
+        (None, Some(palette)) => encode_palette(rgba, width, height, palette, options),
+        (None, None) => encode_png(rgba, width, height, options),
+    }
+}
+
+/// Encodes `pixmap` like `encode` and writes it to `path`.
+pub fn save<P: AsRef<path::Path>>(
+    pixmap: &tiny_skia::Pixmap,
+    path: P,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    let data = encode(pixmap, options)?;
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
+
+/// With `Encoding::reduce`, a smaller lossless color type is tried and kept
+/// when the file is smaller.
+fn encode_png(
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, String> {
+    let reduced = if options.encoding.reduce {
+        reduce(&rgba, width)
+    } else {
+        None
+    };
+
+    let png = write_png(rgba_image(rgba), width, height, options)?;
+
+    match reduced {
+        Some(image) => {
+            let reduced = write_png(image, width, height, options)?;
+            Ok(if reduced.len() < png.len() {
+                reduced
+            } else {
+                png
+            })
+        }
+        None => Ok(png),
+    }
+}
+
+fn encode_palette(
+    mut rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+    palette: Palette,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, String> {
+    if palette.format == PaletteFormat::Gif {
+        for p in rgba.chunks_exact_mut(4) {
+            if p[3] < 128 {
+                p.copy_from_slice(&[0, 0, 0, 0]);
+            } else {
+                p[3] = 255;
+            }
+        }
+    }
+
+    let quantized = quantize::quantize(&rgba, width, palette.colors as usize, palette.dither);
+
+    match palette.format {
+        PaletteFormat::Png => {
+            let image = indexed_image(&quantized.indices, width, &quantized.palette);
+            write_png(image, width, height, options)
+        }
+        PaletteFormat::Gif => encode_gif(&quantized, width, height),
+    }
+}
+
+fn encode_monochrome(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    mono: Monochrome,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, String> {
+    let bits = monochrome::bilevel(rgba, width, mono.threshold, mono.dither);
+
+    match mono.format {
+        MonochromeFormat::Png => {
+            // In grayscale 1 is white.
+            let image = Image {
+                color: png::ColorType::Grayscale,
+                depth: png::BitDepth::One,
+                data: bits.iter().map(|b| !b).collect(),
+                palette: None,
+                trns: None,
+            };
+            write_png(image, width, height, options)
+        }
+        MonochromeFormat::Pbm => Ok(monochrome::pbm(&bits, width, height)),
+        MonochromeFormat::Bits => Ok(bits),
+        MonochromeFormat::EscPos => monochrome::escpos(&bits, width, height),
+        MonochromeFormat::Zpl => Ok(monochrome::zpl(&bits, width)),
+    }
+}
+
+fn encode_gif(quantized: &quantize::Quantized, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let too_large = || "gif size must be at most 65535x65535".to_string();
+    let width = u16::try_from(width).map_err(|_| too_large())?;
+    let height = u16::try_from(height).map_err(|_| too_large())?;
+
+    let palette: Vec<u8> = quantized
+        .palette
+        .iter()
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect();
+
+    let frame = gif::Frame {
+        width,
+        height,
+        transparent: quantized
+            .palette
+            .iter()
+            .position(|c| c[3] == 0)
+            .map(|i| i as u8),
+        buffer: std::borrow::Cow::Borrowed(&quantized.indices),
+        ..gif::Frame::default()
+    };
+
+    let mut data = Vec::new();
+    {
+        let mut encoder =
+            gif::Encoder::new(&mut data, width, height, &palette).map_err(|e| e.to_string())?;
+        encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    }
+
+    Ok(data)
+}
+
+fn write_png(
+    image: Image,
+    width: u32,
+    height: u32,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(image.color);
+        encoder.set_depth(image.depth);
+        if let Some(palette) = image.palette {
+            encoder.set_palette(palette);
+        }
+        if let Some(trns) = image.trns {
+            encoder.set_trns(trns);
+        }
+
+        encoder.set_compression(match options.encoding.compression {
+            Compression::Fast => png::Compression::Fast,
+            Compression::Default => png::Compression::Default,
+            Compression::Best => png::Compression::Best,
+        });
+
+        match options.encoding.filter {
+            Filter::None => encoder.set_filter(png::FilterType::NoFilter),
+            Filter::Sub => encoder.set_filter(png::FilterType::Sub),
+            Filter::Up => encoder.set_filter(png::FilterType::Up),
+            Filter::Avg => encoder.set_filter(png::FilterType::Avg),
+            Filter::Paeth => encoder.set_filter(png::FilterType::Paeth),
+            Filter::Adaptive => encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive),
+        }
+
+        if options.dpi > 0 {
+            let per_meter = (options.dpi as f64 / 0.0254).round() as u32;
+            encoder.set_pixel_dims(Some(png::PixelDimensions {
+                xppu: per_meter,
+                yppu: per_meter,
+                unit: png::Unit::Meter,
+            }));
+        }
+
+        for (keyword, text) in &options.metadata {
+            // tEXt is latin-1 only, iTXt holds any utf-8 text.
+            let added = if text.is_ascii() {
+                encoder.add_text_chunk(keyword.clone(), text.clone())
+            } else {
+                encoder.add_itxt_chunk(keyword.clone(), text.clone())
+            };
+            added.map_err(|e| format!("Error png metadata {:?}: {}", keyword, e))?;
+        }
+
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer
+            .write_image_data(&image.data)
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(png)
+}
+
+/// Picks the smallest lossless color type: a palette when there are at most
+/// 256 colors, then grayscale, then drops an opaque alpha channel. `None`
+/// when RGBA is needed.
+fn reduce(rgba: &[u8], width: u32) -> Option<Image> {
+    let pixels = rgba.chunks_exact(4);
+    let opaque = pixels.clone().all(|p| p[3] == 255);
+    let gray = pixels.clone().all(|p| p[0] == p[1] && p[1] == p[2]);
+
+    let colors = unique_colors(rgba, 256);
+
+    match colors {
+        // An 8-bit palette is not smaller than opaque grayscale.
+        Some(colors) if !(gray && opaque) || colors.len() <= 16 => {
+            Some(indexed(rgba, width, colors))
+        }
+        _ => {
+            let (color, channels): (png::ColorType, &[usize]) = match (gray, opaque) {
+                (true, true) => (png::ColorType::Grayscale, &[0]),
+                (true, false) => (png::ColorType::GrayscaleAlpha, &[0, 3]),
+                (false, true) => (png::ColorType::Rgb, &[0, 1, 2]),
+                (false, false) => return None,
+            };
+
+            let data = rgba
+                .chunks_exact(4)
+                .flat_map(|p| channels.iter().map(move |c| p[*c]))
+                .collect();
+
+            Some(Image {
+                color,
+                depth: png::BitDepth::Eight,
+                data,
+                palette: None,
+                trns: None,
+            })
+        }
+    }
+}
+
+fn rgba_image(rgba: Vec<u8>) -> Image {
+    Image {
+        color: png::ColorType::Rgba,
+        depth: png::BitDepth::Eight,
+        data: rgba,
+        palette: None,
+        trns: None,
+    }
+}
+
+/// The distinct colors, translucent ones first so `tRNS` stays short, or
+/// `None` when there are more than `max`.
+fn unique_colors(rgba: &[u8], max: usize) -> Option<Vec<[u8; 4]>> {
+    let mut seen = HashSet::new();
+    for p in rgba.chunks_exact(4) {
+        let color = [p[0], p[1], p[2], p[3]];
+        if !seen.contains(&color) {
+            if seen.len() == max {
+                return None;
+            }
+            seen.insert(color);
+        }
+    }
+
+    let mut colors: Vec<[u8; 4]> = seen.into_iter().collect();
+    colors.sort_by_key(|c| (c[3] == 255, *c));
+    Some(colors)
+}
+
+/// Indexed image of `colors`, with the smallest bit depth holding them.
+fn indexed(rgba: &[u8], width: u32, colors: Vec<[u8; 4]>) -> Image {
+    let index: HashMap<[u8; 4], u8> = colors
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (*c, i as u8))
+        .collect();
+
+    let indices: Vec<u8> = rgba
+        .chunks_exact(4)
+        .map(|p| index[&[p[0], p[1], p[2], p[3]]])
+        .collect();
+
+    indexed_image(&indices, width, &colors)
+}
+
+/// Packs palette `indices` rows with the smallest bit depth for `colors`.
+fn indexed_image(indices: &[u8], width: u32, colors: &[[u8; 4]]) -> Image {
+    let palette = colors.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+    // Entries after the last translucent one are opaque by default.
+    let translucent = colors
+        .iter()
+        .rposition(|c| c[3] != 255)
+        .map_or(0, |i| i + 1);
+    let trns: Vec<u8> = colors[..translucent].iter().map(|c| c[3]).collect();
+
+    let (depth, bits) = match colors.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+
+    let data = if bits == 8 {
+        indices.to_vec()
+    } else {
+        indices
+            .chunks(width as usize)
+            .flat_map(|row| pack_row(row, bits))
+            .collect()
+    };
+
+    Image {
+        color: png::ColorType::Indexed,
+        depth,
+        data,
+        palette: Some(palette),
+        trns: if trns.is_empty() { None } else { Some(trns) },
+    }
+}
+
+/// Packs values of `bits` bits, most significant first, the last byte is
+/// padded with zeros.
+fn pack_row(row: &[u8], bits: usize) -> Vec<u8> {
+    let per_byte = 8 / bits;
+    row.chunks(per_byte)
+        .map(|values| {
+            values
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, v)| byte | (v << (8 - bits * (i + 1))))
+        })
+        .collect()
+}
@@ -0,0 +1,165 @@
+// Icon containers, both store the rendered sizes as png.
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Ico,
+    Icns,
+}
+
+impl Format {
+    /// Checks the sizes the icon will hold, before they are rendered.
+    pub fn check_sizes(self, sizes: &[u32]) -> Result<(), String> {
+        match self {
+            Format::Ico => check_ico_sizes(sizes),
+            Format::Icns => check_icns_sizes(sizes),
+        }
+    }
+
+    pub fn encode(self, images: &[(u32, Vec<u8>)]) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Ico => encode_ico(images),
+            Format::Icns => encode_icns(images),
+        }
+    }
+}
+
+fn check_ico_sizes(sizes: &[u32]) -> Result<(), String> {
+    if sizes.is_empty() || sizes.len() > u16::MAX as usize {
+        return Err("ico must contain between 1 and 65535 sizes".to_string());
+    }
+
+    for size in sizes {
+        ico_dimension(*size)?;
+    }
+
+    Ok(())
+}
+
+/// Packs square png images into an `.ico` file.
+///
+/// https://en.wikipedia.org/wiki/ICO_(file_format)
+fn encode_ico(images: &[(u32, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let sizes: Vec<u32> = images.iter().map(|(size, _)| *size).collect();
+    check_ico_sizes(&sizes)?;
+
+    const HEADER_LEN: usize = 6;
+    const ENTRY_LEN: usize = 16;
+
+    let mut header = Vec::with_capacity(HEADER_LEN + ENTRY_LEN * images.len());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    // Type 1 is icon.
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&(images.len() as u16).to_le_bytes());
+
+    let mut offset = HEADER_LEN + ENTRY_LEN * images.len();
+    for (size, png) in images {
+        let dimension = ico_dimension(*size)?;
+        check_png_size(png, *size)?;
+
+        header.push(dimension);
+        header.push(dimension);
+        // No color palette, reserved.
+        header.push(0);
+        header.push(0);
+        // Color planes and bits per pixel.
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&32u16.to_le_bytes());
+        header.extend_from_slice(&(png.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(offset as u32).to_le_bytes());
+
+        offset += png.len();
+    }
+
+    let mut ico = header;
+    for (_, png) in images {
+        ico.extend_from_slice(png);
+    }
+
+    Ok(ico)
+}
+
+fn check_icns_sizes(sizes: &[u32]) -> Result<(), String> {
+    if sizes.is_empty() {
+        return Err("icns must contain at least 1 size".to_string());
+    }
+
+    for size in sizes {
+        icns_os_type(*size)?;
+    }
+
+    Ok(())
+}
+
+/// Packs square png images into an `.icns` file.
+///
+/// https://en.wikipedia.org/wiki/Apple_Icon_Image_format
+fn encode_icns(images: &[(u32, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let sizes: Vec<u32> = images.iter().map(|(size, _)| *size).collect();
+    check_icns_sizes(&sizes)?;
+
+    const HEADER_LEN: usize = 8;
+
+    let mut body = Vec::new();
+    for (size, png) in images {
+        let os_type = icns_os_type(*size)?;
+        check_png_size(png, *size)?;
+
+        body.extend_from_slice(os_type);
+        body.extend_from_slice(&((HEADER_LEN + png.len()) as u32).to_be_bytes());
+        body.extend_from_slice(png);
+    }
+
+    let mut icns = Vec::with_capacity(HEADER_LEN + body.len());
+    icns.extend_from_slice(b"icns");
+    icns.extend_from_slice(&((HEADER_LEN + body.len()) as u32).to_be_bytes());
+    icns.extend_from_slice(&body);
+
+    Ok(icns)
+}
+
+fn ico_dimension(size: u32) -> Result<u8, String> {
+    // 0 means 256.
+    match size {
+        1..=255 => Ok(size as u8),
+        256 => Ok(0),
+        _ => Err(format!("ico size must be between 1 and 256, got {}", size)),
+    }
+}
+
+fn icns_os_type(size: u32) -> Result<&'static [u8; 4], String> {
+    match size {
+        16 => Ok(b"icp4"),
+        32 => Ok(b"icp5"),
+        64 => Ok(b"icp6"),
+        128 => Ok(b"ic07"),
+        256 => Ok(b"ic08"),
+        512 => Ok(b"ic09"),
+        1024 => Ok(b"ic10"),
+        _ => Err(format!(
+            "icns size must be one of 16, 32, 64, 128, 256, 512, 1024, got {}",
+            size
+        )),
+    }
+}
+
+/// Checks the entry labelled `size` holds a `size` x `size` png.
+fn check_png_size(png: &[u8], size: u32) -> Result<(), String> {
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err("icon image must be a png".to_string());
+    }
+
+    // After the signature, the IHDR chunk length and type.
+    let dimension = |at: usize| {
+        png.get(at..at + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    match (dimension(16), dimension(20)) {
+        (Some(w), Some(h)) if w == size && h == size => Ok(()),
+        (Some(w), Some(h)) => Err(format!(
+            "icon image must be {}x{}, got {}x{}",
+            size, size, w, h
+        )),
+        _ => Err("icon image must be a png".to_string()),
+    }
+}
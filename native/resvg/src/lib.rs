@@ -1,14 +1,89 @@
 // Based on https://github.com/RazrFalcon/resvg/blob/master/crates/resvg/src/main.rs
 
-use rustler::{Decoder, Encoder, Env, NifResult, NifStruct, Term};
+use rayon::prelude::*;
+use rustler::env::OwnedEnv;
+use rustler::{
+    Atom, Binary, Decoder, Encoder, Env, LocalPid, NifResult, NifStruct, OwnedBinary, Term,
+};
+use std::collections::HashMap;
+use std::io::Read;
+use std::panic;
 use std::path;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicIsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 use usvg::{fontdb, ImageRendering, ShapeRendering, TextRendering};
 
+mod dom;
+mod encoder;
+mod icon;
+mod monochrome;
+mod quantize;
+mod rewrite;
+mod tiles;
+
 mod atoms {
     rustler::atoms! {
       ok,
-      error
+      error,
+      limit_exceeded,
+      timeout,
+      max_input_bytes,
+      max_elements,
+      max_pixels,
+      max_filter_area,
+      allow_dtd,
+      width,
+      height,
+      zoom,
+      ico,
+      icns,
+      contain,
+      cover,
+      fill,
+      top_left,
+      top,
+      top_right,
+      left,
+      center,
+      right,
+      bottom_left,
+      bottom,
+      bottom_right,
+      percent,
+      horizontal,
+      vertical,
+      grid,
+      xyz,
+      mm,
+      cm,
+      inch = "in",
+      pt,
+      compression,
+      filter,
+      reduce,
+      fast,
+      default,
+      best,
+      none,
+      sub,
+      up,
+      avg,
+      paeth,
+      adaptive,
+      colors,
+      dither,
+      format,
+      png,
+      gif,
+      threshold,
+      pbm,
+      bits,
+      escpos,
+      zpl,
+      grayscale,
+      sepia,
+      invert
     }
 }
 
@@ -26,6 +101,302 @@ enum FitTo {
     Zoom(f32),
 }
 
+/// How `FitTo::Size` fills the exact requested size.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FitMode {
+    /// Scale to fit inside, keep aspect ratio and letterbox.
+    Contain,
+    /// Scale to cover, keep aspect ratio and crop.
+    Cover,
+    /// Stretch to the size.
+    Fill,
+}
+
+/// Where the image is placed when `FitMode` letterbox or crop it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Align {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Align {
+    /// Horizontal and vertical share of the free space put before the image.
+    fn factors(&self) -> (f32, f32) {
+        match *self {
+            Align::TopLeft => (0.0, 0.0),
+            Align::Top => (0.5, 0.0),
+            Align::TopRight => (1.0, 0.0),
+            Align::Left => (0.0, 0.5),
+            Align::Center => (0.5, 0.5),
+            Align::Right => (1.0, 0.5),
+            Align::BottomLeft => (0.0, 1.0),
+            Align::Bottom => (0.5, 1.0),
+            Align::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// Output size bounds applied after `FitTo`.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+struct SizeBounds {
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    min_width: Option<u32>,
+    min_height: Option<u32>,
+}
+
+impl SizeBounds {
+    /// Scales `size` and `transform` down to the max bounds, or up to the min
+    /// bounds. The aspect ratio is kept, max bounds win over min bounds.
+    fn bound(
+        &self,
+        size: tiny_skia::IntSize,
+        transform: tiny_skia::Transform,
+    ) -> Option<(tiny_skia::IntSize, tiny_skia::Transform)> {
+        let (w, h) = (size.width() as f32, size.height() as f32);
+        let ratio = |bound: Option<u32>, v: f32| bound.map(|b| b as f32 / v);
+
+        let down = [ratio(self.max_width, w), ratio(self.max_height, h)]
+            .iter()
+            .flatten()
+            .fold(1.0f32, |scale, r| scale.min(*r));
+
+        let up = [ratio(self.min_width, w), ratio(self.min_height, h)]
+            .iter()
+            .flatten()
+            .fold(1.0f32, |scale, r| scale.max(*r));
+
+        let scale = if down < 1.0 {
+            down
+        } else {
+            up.min(self.max_scale(w, h))
+        };
+
+        if scale == 1.0 {
+            return Some((size, transform));
+        }
+
+        let bounded = size.scale_by(scale)?;
+        let transform =
+            transform.post_scale(bounded.width() as f32 / w, bounded.height() as f32 / h);
+
+        Some((bounded, transform))
+    }
+
+    /// Largest scale staying within the max bounds.
+    fn max_scale(&self, w: f32, h: f32) -> f32 {
+        let width = self.max_width.map_or(f32::MAX, |b| b as f32 / w);
+        let height = self.max_height.map_or(f32::MAX, |b| b as f32 / h);
+        width.min(height)
+    }
+}
+
+/// Color transform applied to the rendered pixels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorTransform {
+    Grayscale,
+    Sepia,
+    Invert,
+    /// Rows of `r g b a offset` like `feColorMatrix`, on 0..1 channels.
+    Matrix([f32; 20]),
+}
+
+impl ColorTransform {
+    #[rustfmt::skip]
+    fn matrix(&self) -> [f32; 20] {
+        match *self {
+            ColorTransform::Grayscale => [
+                0.2126, 0.7152, 0.0722, 0.0, 0.0,
+                0.2126, 0.7152, 0.0722, 0.0, 0.0,
+                0.2126, 0.7152, 0.0722, 0.0, 0.0,
+                0.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            ColorTransform::Sepia => [
+                0.393, 0.769, 0.189, 0.0, 0.0,
+                0.349, 0.686, 0.168, 0.0, 0.0,
+                0.272, 0.534, 0.131, 0.0, 0.0,
+                0.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            ColorTransform::Invert => [
+                -1.0, 0.0, 0.0, 0.0, 1.0,
+                0.0, -1.0, 0.0, 0.0, 1.0,
+                0.0, 0.0, -1.0, 0.0, 1.0,
+                0.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            ColorTransform::Matrix(m) => m,
+        }
+    }
+
+    fn apply(&self, pixmap: &mut tiny_skia::Pixmap) {
+        let m = self.matrix();
+
+        for pixel in pixmap.pixels_mut() {
+            let c = pixel.demultiply();
+            let v = [
+                c.red() as f32 / 255.0,
+                c.green() as f32 / 255.0,
+                c.blue() as f32 / 255.0,
+                c.alpha() as f32 / 255.0,
+            ];
+
+            let channel = |row: usize| {
+                let r = &m[row * 5..row * 5 + 5];
+                let value = r[0] * v[0] + r[1] * v[1] + r[2] * v[2] + r[3] * v[3] + r[4];
+                (value.clamp(0.0, 1.0) * 255.0).round() as u8
+            };
+
+            *pixel = tiny_skia::ColorU8::from_rgba(channel(0), channel(1), channel(2), channel(3))
+                .premultiply();
+        }
+    }
+}
+
+/// Output width or height, in pixels or in a physical unit.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Dimension {
+    Px(u32),
+    Mm(f32),
+    Cm(f32),
+    In(f32),
+    Pt(f32),
+}
+
+impl Dimension {
+    /// Pixels at `dpi` dots per inch.
+    fn to_px(self, dpi: u32) -> u32 {
+        let inches = match self {
+            Dimension::Px(px) => return px,
+            Dimension::Mm(v) => v / 25.4,
+            Dimension::Cm(v) => v / 2.54,
+            Dimension::In(v) => v,
+            Dimension::Pt(v) => v / 72.0,
+        };
+        (inches * dpi as f32).round().max(0.0) as u32
+    }
+}
+
+/// Area of the drawing to render, in the svg user units.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Region(tiny_skia::NonZeroRect);
+
+/// Rotation in degrees clockwise.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Angle(f32);
+
+/// Mirrors the rendered image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+}
+
+/// Flip then rotation applied to the rendered image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Orientation {
+    rotate: Angle,
+    flip: Option<Flip>,
+}
+
+impl Orientation {
+    /// Flips and rotates `transform` around the image center. The canvas
+    /// expands to hold the whole rotated image.
+    fn orient(
+        &self,
+        size: tiny_skia::IntSize,
+        transform: tiny_skia::Transform,
+    ) -> Option<(tiny_skia::IntSize, tiny_skia::Transform)> {
+        let (w, h) = (size.width() as f32, size.height() as f32);
+
+        let transform = match self.flip {
+            Some(Flip::Horizontal) => transform.post_scale(-1.0, 1.0).post_translate(w, 0.0),
+            Some(Flip::Vertical) => transform.post_scale(1.0, -1.0).post_translate(0.0, h),
+            None => transform,
+        };
+
+        let degrees = self.rotate.0.rem_euclid(360.0);
+        if degrees == 0.0 {
+            return Some((size, transform));
+        }
+
+        let rotated = if degrees == 90.0 || degrees == 270.0 {
+            tiny_skia::IntSize::from_wh(size.height(), size.width())?
+        } else if degrees == 180.0 {
+            size
+        } else {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            // Tolerate float noise so a 1px edge is not added.
+            let expand = |v: f32| (v - 0.001).ceil() as u32;
+            tiny_skia::IntSize::from_wh(
+                expand(w * cos.abs() + h * sin.abs()),
+                expand(w * sin.abs() + h * cos.abs()),
+            )?
+        };
+
+        let transform = transform
+            .post_translate(-w / 2.0, -h / 2.0)
+            .post_rotate(degrees)
+            .post_translate(rotated.width() as f32 / 2.0, rotated.height() as f32 / 2.0);
+
+        Some((rotated, transform))
+    }
+}
+
+/// A padding side, in pixels or in percent of the rendered image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Length {
+    Px(f32),
+    Percent(f32),
+}
+
+impl Length {
+    fn resolve(&self, reference: u32) -> u32 {
+        let value = match *self {
+            Length::Px(v) => v,
+            Length::Percent(p) => reference as f32 * p / 100.0,
+        };
+        value.max(0.0).round() as u32
+    }
+}
+
+/// Space added around the rendered image, filled with the background.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Padding {
+    top: Length,
+    right: Length,
+    bottom: Length,
+    left: Length,
+}
+
+impl Padding {
+    /// Enlarges `size` and moves `transform` by the padding. Percent are
+    /// relative to the width for left and right, to the height otherwise.
+    fn pad(
+        &self,
+        size: tiny_skia::IntSize,
+        transform: tiny_skia::Transform,
+    ) -> Option<(tiny_skia::IntSize, tiny_skia::Transform)> {
+        let (w, h) = (size.width(), size.height());
+        let top = self.top.resolve(h);
+        let right = self.right.resolve(w);
+        let bottom = self.bottom.resolve(h);
+        let left = self.left.resolve(w);
+
+        let padded = tiny_skia::IntSize::from_wh(
+            w.checked_add(left)?.checked_add(right)?,
+            h.checked_add(top)?.checked_add(bottom)?,
+        )?;
+
+        Some((padded, transform.post_translate(left as f32, top as f32)))
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 enum InputFrom {
     File(path::PathBuf),
@@ -55,11 +426,70 @@ impl FitTo {
             size2.height() / size1.height(),
         )
     }
+
+    /// Like `fit_to_size` and `fit_to_transform`, but `FitTo::Size` produces
+    /// exactly the requested size when a `FitMode` is given.
+    fn fit_to_size_and_transform(
+        &self,
+        size: tiny_skia::IntSize,
+        fit: Option<FitMode>,
+        align: Align,
+    ) -> Option<(tiny_skia::IntSize, tiny_skia::Transform)> {
+        let (w, h, fit) = match (*self, fit) {
+            (FitTo::Size(w, h), Some(fit)) => (w, h, fit),
+            _ => return Some((self.fit_to_size(size)?, self.fit_to_transform(size))),
+        };
+
+        let target = tiny_skia::IntSize::from_wh(w, h)?;
+
+        let sx = w as f32 / size.width() as f32;
+        let sy = h as f32 / size.height() as f32;
+        let (sx, sy) = match fit {
+            FitMode::Contain => (sx.min(sy), sx.min(sy)),
+            FitMode::Cover => (sx.max(sy), sx.max(sy)),
+            FitMode::Fill => (sx, sy),
+        };
+
+        let (ax, ay) = align.factors();
+        let dx = (w as f32 - size.width() as f32 * sx) * ax;
+        let dy = (h as f32 - size.height() as f32 * sy) * ay;
+
+        Some((
+            target,
+            tiny_skia::Transform::from_row(sx, 0.0, 0.0, sy, dx, dy),
+        ))
+    }
+}
+
+/// Error returned to Elixir as `{:error, reason}`.
+enum Error {
+    /// Human readable message, most errors are reported this way.
+    Message(String),
+    /// One of the `:limits` was exceeded, holds the limit name.
+    LimitExceeded(Atom),
+    /// The `:timeout` elapsed before the job was done.
+    Timeout,
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Message(message)
+    }
+}
+
+impl Encoder for Error {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            Error::Message(message) => message.encode(env),
+            Error::LimitExceeded(limit) => (atoms::limit_exceeded(), *limit).encode(env),
+            Error::Timeout => atoms::timeout().encode(env),
+        }
+    }
 }
 
 macro_rules! try_or_return_elixir_err {
     ($expression:expr, $env:expr) => {
-        match $expression.map_err(|e| e.to_string()) {
+        match $expression.map_err(Error::from) {
             Ok(val) => val,
             Err(err) => return Ok((atoms::error(), err).encode($env)),
         }
@@ -81,6 +511,11 @@ pub struct ImageRenderingWrapper {
     value: ImageRendering,
 }
 
+#[derive(Clone)]
+pub struct ImageDataWrapper {
+    value: Vec<u8>,
+}
+
 struct FontProperties {
     font_files: Vec<path::PathBuf>,
     font_dirs: Vec<path::PathBuf>,
@@ -102,7 +537,7 @@ impl FontProperties {
             cursive_family: parsed_options.cursive_family.clone(),
             fantasy_family: parsed_options.fantasy_family.clone(),
             monospace_family: parsed_options.monospace_family.clone(),
-            skip_system_fonts: parsed_options.skip_system_fonts.clone(),
+            skip_system_fonts: parsed_options.skip_system_fonts,
         }
     }
 }
@@ -110,16 +545,41 @@ impl FontProperties {
 #[derive(NifStruct)]
 #[module = "Resvg.Options"]
 pub struct Options {
-    width: Option<u32>,
-    height: Option<u32>,
+    width: Option<Dimension>,
+    height: Option<Dimension>,
     zoom: Option<f32>,
+    fit: Option<FitMode>,
+    align: Align,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    min_width: Option<u32>,
+    min_height: Option<u32>,
+    region: Option<Region>,
+    rotate: Angle,
+    flip: Option<Flip>,
+    padding: Option<Padding>,
+    color_transform: Option<ColorTransform>,
     dpi: u32,
+    png_metadata: PngMetadata,
+    png: encoder::Encoding,
+    palette: Option<encoder::Palette>,
+    monochrome: Option<monochrome::Monochrome>,
     background: Option<String>,
+    stylesheet: Option<String>,
+    current_color: Option<String>,
+    variables: StringMap,
+    hide_ids: Vec<String>,
+    show_only_ids: Option<Vec<String>>,
+    text: StringMap,
+    patch: Vec<rewrite::Patch>,
     languages: Vec<String>,
     shape_rendering: ShapeRenderingWrapper,
     text_rendering: TextRenderingWrapper,
     image_rendering: ImageRenderingWrapper,
     resources_dir: Option<String>,
+    images: HashMap<String, ImageDataWrapper>,
+    limits: Limits,
+    timeout: Option<u64>,
 
     font_family: Option<String>,
     font_size: u32,
@@ -133,6 +593,14 @@ pub struct Options {
     skip_system_fonts: bool,
 }
 
+/// Png text chunks, keyword and text.
+#[derive(Clone)]
+pub struct PngMetadata(Vec<(String, String)>);
+
+/// Map of strings, the keys may be atoms.
+#[derive(Clone)]
+pub struct StringMap(HashMap<String, String>);
+
 #[derive(NifStruct)]
 #[module = "Resvg.Native.Node"]
 struct Node {
@@ -143,14 +611,57 @@ struct Node {
     pub height: f32,
 }
 
+/// Safety limits for untrusted input. `None` means unlimited.
+#[derive(Clone, Copy)]
+pub struct Limits {
+    /// Size of the (decompressed) svg source.
+    max_input_bytes: Option<u64>,
+    /// Number of XML elements before conversion and of nodes after it.
+    max_elements: Option<u64>,
+    /// Output `width * height`.
+    max_pixels: Option<u64>,
+    /// Area of a single filter region in output pixels.
+    max_filter_area: Option<u64>,
+    allow_dtd: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_input_bytes: None,
+            max_elements: None,
+            max_pixels: None,
+            max_filter_area: None,
+            allow_dtd: true,
+        }
+    }
+}
+
 struct ParsedOptions<'a> {
     // TODO implements these
     // export_id: Option<String>,
     // export_area_page: bool,
     // export_area_drawing: bool,
     usvg: usvg::Options<'a>,
+    /// The fontdb is already loaded and may be shared with other renders.
+    fonts_loaded: bool,
+    /// Error met while resolving an image, like a relative href while
+    /// `resources_dir` is not set or a nested svg exceeding the limits.
+    href_error: Arc<Mutex<Option<Error>>>,
     fit_to: FitTo,
+    fit: Option<FitMode>,
+    align: Align,
+    size_bounds: SizeBounds,
+    region: Option<Region>,
+    /// Maps the user units of the parsed svg to its canvas, set by `parse_svg`.
+    view_box: tiny_skia::Transform,
+    orientation: Orientation,
+    padding: Option<Padding>,
+    color_transform: Option<ColorTransform>,
+    rewrite: rewrite::Rewrite,
     background: Option<svgtypes::Color>,
+    output: encoder::EncodeOptions,
+    limits: Limits,
     serif_family: Option<String>,
     sans_serif_family: Option<String>,
     cursive_family: Option<String>,
@@ -161,163 +672,361 @@ struct ParsedOptions<'a> {
     skip_system_fonts: bool,
 }
 
-#[rustler::nif]
+#[rustler::nif(schedule = "DirtyCpu")]
 pub fn svg_to_png<'a>(
     env: Env<'a>,
     in_svg: String,
     out_png: String,
     options: Options,
 ) -> NifResult<Term<'a>> {
-    let input_from = InputFrom::File(path::PathBuf::from(&in_svg));
+    let timeout = options.timeout;
 
-    let mut parsed_options = try_or_return_elixir_err!(parse_options(input_from, options), env);
-    let font_properties = FontProperties::from_parsed_options(&parsed_options);
+    try_or_return_elixir_err!(
+        run_with_timeout(timeout, move |cancel| {
+            let input_from = InputFrom::File(path::PathBuf::from(&in_svg));
 
-    let mut svg_data = try_or_return_elixir_err!(
-        std::fs::read(&in_svg).map_err(|e| format!("Error loading svg file: {}", e)),
-        env
-    );
+            let mut parsed_options = parse_options(input_from, options)?;
 
-    if svg_data.starts_with(&[0x1f, 0x8b]) {
-        svg_data = try_or_return_elixir_err!(
-            usvg::decompress_svgz(&svg_data).map_err(|e| e.to_string()),
-            env
-        );
-    };
+            let svg_data = read_svg_file(&in_svg, &parsed_options.limits)?;
+
+            let svg_string = std::str::from_utf8(&svg_data)
+                .map_err(|_| "provided data has not an UTF-8 encoding".to_string())?;
+
+            let tree = parse_svg(svg_string, &mut parsed_options)?;
+            cancel.check()?;
+
+            let img = render_svg(&parsed_options, &tree, cancel)?;
+            cancel.check()?;
 
-    let svg_string = try_or_return_elixir_err!(
-        std::str::from_utf8(&svg_data)
-            .map_err(|_| "provided data has not an UTF-8 encoding".to_string()),
+            Ok(encoder::save(&img, out_png, &parsed_options.output)?)
+        }),
         env
     );
 
-    let xml_opt = usvg::roxmltree::ParsingOptions {
-        allow_dtd: true,
-        ..Default::default()
-    };
+    Ok(atoms::ok().encode(env))
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn svg_string_to_png<'a>(
+    env: Env<'a>,
+    svg_string: String,
+    out_png: String,
+    options: Options,
+) -> NifResult<Term<'a>> {
+    let timeout = options.timeout;
+
+    try_or_return_elixir_err!(
+        run_with_timeout(timeout, move |cancel| {
+            let mut parsed_options = parse_options(InputFrom::Text, options)?;
 
-    let xml_tree = try_or_return_elixir_err!(
-        usvg::roxmltree::Document::parse_with_options(svg_string, xml_opt)
-            .map_err(|e| e.to_string()),
+            let tree = parse_svg(&svg_string, &mut parsed_options)?;
+            cancel.check()?;
+
+            let img = render_svg(&parsed_options, &tree, cancel)?;
+            cancel.check()?;
+
+            Ok(encoder::save(&img, out_png, &parsed_options.output)?)
+        }),
         env
     );
 
-    // fontdb initialization is pretty expensive, so perform it only when needed.
-    let has_text_nodes = xml_tree
-        .descendants()
-        .any(|n| n.has_tag_name(("http://www.w3.org/2000/svg", "text")));
+    Ok(atoms::ok().encode(env))
+}
 
-    if has_text_nodes {
-        match load_fonts(&font_properties, parsed_options.usvg.fontdb_mut()) {
-            Ok(_) => (),
-            Err(error) => return Ok((atoms::error(), error).encode(env)),
-        };
-    }
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn svg_string_to_png_buffer<'a>(
+    env: Env<'a>,
+    svg_string: String,
+    options: Options,
+) -> NifResult<Term<'a>> {
+    let timeout = options.timeout;
 
-    let tree = try_or_return_elixir_err!(
-        usvg::Tree::from_xmltree(&xml_tree, &parsed_options.usvg).map_err(|e| e.to_string()),
+    let buf = try_or_return_elixir_err!(
+        run_with_timeout(timeout, move |cancel| {
+            let parsed_options = parse_options(InputFrom::Text, options)?;
+            render_png_buffer(&svg_string, parsed_options, cancel)
+        }),
         env
     );
 
-    let img = try_or_return_elixir_err!(render_svg(&parsed_options, &tree), env);
-
-    match img.save_png(out_png).map_err(|e| e.to_string()) {
-        Ok(_) => Ok(atoms::ok().encode(env)),
-        Err(error_msg) => return Ok((atoms::error(), error_msg).encode(env)),
-    }
+    Ok((atoms::ok(), buf).encode(env))
 }
 
 #[rustler::nif]
-pub fn svg_string_to_png<'a>(
+pub fn render_async<'a>(
     env: Env<'a>,
     svg_string: String,
-    out_png: String,
+    options: Options,
+    pid: LocalPid,
+    reference: Term<'a>,
+) -> Term<'a> {
+    let mut owned_env = OwnedEnv::new();
+    let reference = owned_env.save(reference);
+
+    rayon::spawn(move || {
+        // A panicking rayon job aborts the process, so the whole VM with it.
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            parse_options(InputFrom::Text, options)
+                .map_err(Error::from)
+                .and_then(|parsed_options| {
+                    render_png_buffer(&svg_string, parsed_options, &Cancel::never())
+                })
+        }))
+        .unwrap_or_else(|_| Err(Error::Message("render panicked".to_string())));
+
+        // The caller may be dead already, nobody is waiting for the result then.
+        let _ = owned_env.send_and_clear(&pid, |env| (reference.load(env), result));
+    });
+
+    atoms::ok().encode(env)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn render_sizes<'a>(
+    env: Env<'a>,
+    svg_string: String,
+    sizes: Vec<FitTo>,
     options: Options,
 ) -> NifResult<Term<'a>> {
-    let input_from = InputFrom::Text;
+    let timeout = options.timeout;
 
-    let mut parsed_options = try_or_return_elixir_err!(parse_options(input_from, options), env);
-    let font_properties = FontProperties::from_parsed_options(&parsed_options);
+    let buffers = try_or_return_elixir_err!(
+        run_with_timeout(timeout, move |cancel| {
+            let mut parsed_options = parse_options(InputFrom::Text, options)?;
 
-    let xml_opt = usvg::roxmltree::ParsingOptions {
-        allow_dtd: true,
-        ..Default::default()
-    };
+            let tree = parse_svg(&svg_string, &mut parsed_options)?;
 
-    let xml_tree = try_or_return_elixir_err!(
-        usvg::roxmltree::Document::parse_with_options(&svg_string, xml_opt)
-            .map_err(|e| e.to_string()),
+            sizes
+                .into_par_iter()
+                .map(|fit_to| {
+                    cancel.check()?;
+
+                    let img = render_svg_with_fit(&parsed_options, fit_to, &tree, cancel)?;
+
+                    Ok(encoder::encode(&img, &parsed_options.output)?)
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        }),
         env
     );
 
-    let has_text_nodes = xml_tree
-        .descendants()
-        .any(|n| n.has_tag_name(("http://www.w3.org/2000/svg", "text")));
+    Ok((atoms::ok(), buffers).encode(env))
+}
 
-    if has_text_nodes {
-        match load_fonts(&font_properties, parsed_options.usvg.fontdb_mut()) {
-            Ok(_) => (),
-            Err(error) => return Ok((atoms::error(), error).encode(env)),
-        };
-    }
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn render_icon<'a>(
+    env: Env<'a>,
+    svg_string: String,
+    format: Atom,
+    sizes: Vec<u32>,
+    options: Options,
+) -> NifResult<Term<'a>> {
+    let timeout = options.timeout;
+
+    let format = if format == atoms::ico() {
+        icon::Format::Ico
+    } else if format == atoms::icns() {
+        icon::Format::Icns
+    } else {
+        return Err(rustler::Error::BadArg);
+    };
+
+    let icon = try_or_return_elixir_err!(
+        run_with_timeout(timeout, move |cancel| {
+            // Nothing is rendered for sizes the icon can't hold.
+            format.check_sizes(&sizes)?;
+
+            let mut parsed_options = parse_options(InputFrom::Text, options)?;
+            // Icons are square, keep the whole svg by default.
+            parsed_options.fit.get_or_insert(FitMode::Contain);
+            parsed_options.size_bounds = SizeBounds::default();
+            parsed_options.output.force_png();
+
+            let tree = parse_svg(&svg_string, &mut parsed_options)?;
+
+            let images = sizes
+                .into_par_iter()
+                .map(|size| {
+                    cancel.check()?;
+
+                    let (img_size, transform) =
+                        output_size_and_transform(&parsed_options, FitTo::Size(size, size), &tree)?;
+                    let (img_size, transform) = fit_in_square(img_size, transform, size)?;
+
+                    let img = render_pixmap(&parsed_options, &tree, img_size, transform, cancel)?;
+                    let png = encoder::encode(&img, &parsed_options.output)?;
+
+                    Ok((size, png))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
 
-    let tree = try_or_return_elixir_err!(
-        usvg::Tree::from_xmltree(&xml_tree, &parsed_options.usvg).map_err(|e| e.to_string()),
+            Ok(format.encode(&images)?)
+        }),
         env
     );
 
-    let img = try_or_return_elixir_err!(render_svg(&parsed_options, &tree), env);
-
-    match img.save_png(out_png).map_err(|e| e.to_string()) {
-        Ok(_) => Ok(atoms::ok().encode(env)),
-        Err(error_msg) => return Ok((atoms::error(), error_msg).encode(env)),
-    }
+    Ok((atoms::ok(), icon).encode(env))
 }
 
-#[rustler::nif]
-pub fn svg_string_to_png_buffer<'a>(
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn render_batch<'a>(
     env: Env<'a>,
-    svg_string: String,
-    options: Options,
+    items: Vec<(String, Options)>,
+    batch_options: Options,
 ) -> NifResult<Term<'a>> {
-    let input_from = InputFrom::Text;
+    let timeout = batch_options.timeout;
 
-    let mut parsed_options = try_or_return_elixir_err!(parse_options(input_from, options), env);
-    let font_properties = FontProperties::from_parsed_options(&parsed_options);
+    let results = try_or_return_elixir_err!(
+        run_with_timeout(timeout, move |cancel| {
+            // Loading fonts is the most expensive part of a small render,
+            // do it once for the whole batch.
+            let fontdb = Arc::new(load_fontdb(batch_options)?);
 
-    let xml_opt = usvg::roxmltree::ParsingOptions {
-        allow_dtd: true,
-        ..Default::default()
-    };
+            let results: Vec<Result<Vec<u8>, Error>> = items
+                .into_par_iter()
+                .map(|(svg_string, options)| {
+                    cancel.check()?;
+
+                    let mut parsed_options = parse_options(InputFrom::Text, options)?;
+                    parsed_options.usvg.fontdb = fontdb.clone();
+                    parsed_options.fonts_loaded = true;
 
-    let xml_tree = try_or_return_elixir_err!(
-        usvg::roxmltree::Document::parse_with_options(&svg_string, xml_opt)
-            .map_err(|e| e.to_string()),
+                    render_png_buffer(&svg_string, parsed_options, cancel)
+                })
+                .collect();
+
+            Ok(results)
+        }),
         env
     );
 
-    let has_text_nodes = xml_tree
-        .descendants()
-        .any(|n| n.has_tag_name(("http://www.w3.org/2000/svg", "text")));
+    Ok((atoms::ok(), results).encode(env))
+}
 
-    if has_text_nodes {
-        match load_fonts(&font_properties, parsed_options.usvg.fontdb_mut()) {
-            Ok(_) => (),
-            Err(error) => return Ok((atoms::error(), error).encode(env)),
-        };
+#[rustler::nif(schedule = "DirtyCpu")]
+pub fn render_tiles<'a>(
+    env: Env<'a>,
+    svg_string: String,
+    tile_size: u32,
+    layout: Atom,
+    output_dir: Option<String>,
+    options: Options,
+) -> NifResult<Term<'a>> {
+    let timeout = options.timeout;
+
+    let layout = if layout == atoms::grid() {
+        tiles::Layout::Grid
+    } else if layout == atoms::xyz() {
+        tiles::Layout::Xyz
+    } else {
+        return Err(rustler::Error::BadArg);
+    };
+
+    if tile_size == 0 {
+        return Err(rustler::Error::BadArg);
     }
 
-    let tree = try_or_return_elixir_err!(
-        usvg::Tree::from_xmltree(&xml_tree, &parsed_options.usvg).map_err(|e| e.to_string()),
+    let write_files = output_dir.is_some();
+
+    let rendered = try_or_return_elixir_err!(
+        run_with_timeout(timeout, move |cancel| {
+            let mut parsed_options = parse_options(InputFrom::Text, options)?;
+
+            let tree = parse_svg(&svg_string, &mut parsed_options)?;
+
+            // The full size render may be too large for a pixmap, only the
+            // tiles are allocated.
+            let (size, transform) =
+                output_size_and_transform(&parsed_options, parsed_options.fit_to, &tree)?;
+
+            let output_dir = output_dir.map(path::PathBuf::from);
+
+            tiles::tiles(layout, size.width(), size.height(), tile_size)
+                .into_par_iter()
+                .map(|tile| {
+                    cancel.check()?;
+
+                    let tile_transform =
+                        transform.post_scale(tile.scale, tile.scale).post_translate(
+                            -((tile.x * tile_size) as f32),
+                            -((tile.y * tile_size) as f32),
+                        );
+
+                    // The filters reaching the tile are rendered whole on a
+                    // larger pixmap, then cropped to the tile.
+                    let filters = filter_bounds(tree.root(), tile_transform);
+                    let margin = tile_margin(filters, tile.width, tile.height);
+                    let size = tiny_skia::IntSize::from_wh(
+                        tile.width.saturating_add(margin.saturating_mul(2)),
+                        tile.height.saturating_add(margin.saturating_mul(2)),
+                    )
+                    .ok_or_else(|| "target size is zero".to_string())?;
+                    let margin_transform =
+                        tile_transform.post_translate(margin as f32, margin as f32);
+
+                    let mut img =
+                        render_pixmap(&parsed_options, &tree, size, margin_transform, cancel)?;
+                    if margin > 0 {
+                        img = tiny_skia::IntRect::from_xywh(
+                            margin as i32,
+                            margin as i32,
+                            tile.width,
+                            tile.height,
+                        )
+                        .and_then(|rect| img.clone_rect(rect))
+                        .ok_or_else(|| "target size is too large".to_string())?;
+                    }
+
+                    match output_dir {
+                        Some(ref dir) => {
+                            let path = tile.path(layout, dir, parsed_options.output.extension());
+                            if let Some(parent) = path.parent() {
+                                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                            }
+                            encoder::save(&img, path, &parsed_options.output)?;
+                            Ok((tile, None))
+                        }
+                        None => {
+                            let png = encoder::encode(&img, &parsed_options.output)?;
+                            Ok((tile, Some(png)))
+                        }
+                    }
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        }),
         env
     );
 
-    let img = try_or_return_elixir_err!(render_svg(&parsed_options, &tree), env);
-
-    match img.encode_png().map_err(|e| e.to_string()) {
-        Ok(buf) => Ok((atoms::ok(), buf).encode(env)),
-        Err(error_msg) => return Ok((atoms::error(), error_msg).encode(env)),
+    if write_files {
+        return Ok(atoms::ok().encode(env));
     }
+
+    let encoded = rendered
+        .into_iter()
+        .filter_map(|(tile, png)| {
+            let png = png?;
+            Some(match layout {
+                tiles::Layout::Grid => (tile.x, tile.y, png).encode(env),
+                tiles::Layout::Xyz => (tile.z, tile.x, tile.y, png).encode(env),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok((atoms::ok(), encoded).encode(env))
+}
+
+fn render_png_buffer(
+    svg_string: &str,
+    mut parsed_options: ParsedOptions,
+    cancel: &Cancel,
+) -> Result<Vec<u8>, Error> {
+    let tree = parse_svg(svg_string, &mut parsed_options)?;
+    cancel.check()?;
+
+    let img = render_svg(&parsed_options, &tree, cancel)?;
+    cancel.check()?;
+
+    Ok(encoder::encode(&img, &parsed_options.output)?)
 }
 
 #[rustler::nif]
@@ -363,67 +1072,39 @@ pub fn list_fonts<'a>(env: Env<'a>, options: Options) -> NifResult<Term<'a>> {
     Ok((atoms::ok(), font_info_strings).encode(env))
 }
 
-#[rustler::nif]
+#[rustler::nif(schedule = "DirtyCpu")]
 pub fn query_all<'a>(env: Env<'a>, in_svg: String, options: Options) -> NifResult<Term<'a>> {
-    let input_from = InputFrom::File(path::PathBuf::from(&in_svg));
+    let timeout = options.timeout;
 
-    let mut parsed_options = try_or_return_elixir_err!(parse_options(input_from, options), env);
-    let font_properties = FontProperties::from_parsed_options(&parsed_options);
+    let result = try_or_return_elixir_err!(
+        run_with_timeout(timeout, move |_cancel| {
+            let input_from = InputFrom::File(path::PathBuf::from(&in_svg));
 
-    let mut svg_data = try_or_return_elixir_err!(
-        std::fs::read(&in_svg).map_err(|e| format!("Error loading svg file: {}", e)),
-        env
-    );
+            let mut parsed_options = parse_options(input_from, options)?;
 
-    if svg_data.starts_with(&[0x1f, 0x8b]) {
-        svg_data = try_or_return_elixir_err!(
-            usvg::decompress_svgz(&svg_data).map_err(|e| e.to_string()),
-            env
-        );
-    };
+            let svg_data = read_svg_file(&in_svg, &parsed_options.limits)?;
 
-    let svg_string = try_or_return_elixir_err!(
-        std::str::from_utf8(&svg_data)
-            .map_err(|_| "provided data has not an UTF-8 encoding".to_string()),
-        env
-    );
+            let svg_string = std::str::from_utf8(&svg_data)
+                .map_err(|_| "provided data has not an UTF-8 encoding".to_string())?;
 
-    let xml_opt = usvg::roxmltree::ParsingOptions {
-        allow_dtd: true,
-        ..Default::default()
-    };
+            let tree = parse_svg(svg_string, &mut parsed_options)?;
 
-    let xml_tree = try_or_return_elixir_err!(
-        usvg::roxmltree::Document::parse_with_options(svg_string, xml_opt)
-            .map_err(|e| e.to_string()),
+            Ok(query_nodes(&tree))
+        }),
         env
     );
 
-    // fontdb initialization is pretty expensive, so perform it only when needed.
-    let has_text_nodes = xml_tree
-        .descendants()
-        .any(|n| n.has_tag_name(("http://www.w3.org/2000/svg", "text")));
-
-    if has_text_nodes {
-        match load_fonts(&font_properties, parsed_options.usvg.fontdb_mut()) {
-            Ok(_) => (),
-            Err(error) => return Ok((atoms::error(), error).encode(env)),
-        };
-    }
-
-    let tree = try_or_return_elixir_err!(
-        usvg::Tree::from_xmltree(&xml_tree, &parsed_options.usvg).map_err(|e| e.to_string()),
-        env
-    );
+    Ok(result.encode(env))
+}
 
+fn query_nodes(tree: &usvg::Tree) -> Vec<Node> {
     fn round_len(v: f32) -> f32 {
         (v * 1000.0).round() / 1000.0
     }
 
-    let result: Vec<Node> = tree
-        .root()
+    tree.root()
         .children()
-        .into_iter()
+        .iter()
         .filter_map(|node| {
             if node.id().is_empty() {
                 None
@@ -441,42 +1122,253 @@ pub fn query_all<'a>(env: Env<'a>, in_svg: String, options: Options) -> NifResul
                 Some(node)
             }
         })
-        .collect();
-
-    Ok(result.encode(env))
+        .collect()
 }
 
-fn parse_options<'a>(in_svg: InputFrom, options: Options) -> Result<ParsedOptions<'a>, String> {
-    let mut fit_to = FitTo::Original;
-    let mut default_size = usvg::Size::from_wh(100.0, 100.0).unwrap();
-    if let (Some(w), Some(h)) = (options.width, options.height) {
-        default_size = usvg::Size::from_wh(w as f32, h as f32).unwrap();
-        fit_to = FitTo::Size(w, h);
-    } else if let Some(w) = options.width {
-        default_size = usvg::Size::from_wh(w as f32, 100.0).unwrap();
-        fit_to = FitTo::Width(w);
-    } else if let Some(h) = options.height {
-        default_size = usvg::Size::from_wh(100.0, h as f32).unwrap();
-        fit_to = FitTo::Height(h);
-    } else if let Some(z) = options.zoom {
-        fit_to = FitTo::Zoom(z);
+/// Checked by a job between its steps, set once its caller gave up waiting.
+struct Cancel(Option<Arc<AtomicBool>>);
+
+impl Cancel {
+    fn new() -> Self {
+        Cancel(Some(Arc::new(AtomicBool::new(false))))
     }
 
-    let resources_dir = match options.resources_dir {
-        Some(v) => Some(path::PathBuf::from(v)),
-        None => match in_svg {
-            InputFrom::File(ref path) => std::fs::canonicalize(&path)
-                .ok()
-                .and_then(|p| p.parent().map(|p| p.to_path_buf())),
+    /// For jobs without a timeout, nobody can give up on them.
+    fn never() -> Self {
+        Cancel(None)
+    }
 
-            InputFrom::Text | InputFrom::Empty => {
-                return Err(
-                    "Make sure to set resources_dir when you are not passing a svg path"
-                        .to_string(),
-                )
-            }
-        },
-    };
+    fn is_cancellable(&self) -> bool {
+        self.0.is_some()
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        match self.0 {
+            Some(ref cancelled) if cancelled.load(Ordering::Relaxed) => Err(Error::Timeout),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Abandoned jobs still running. It may be briefly negative while a job
+/// ends just as its caller gives up.
+static ABANDONED_RENDERS: AtomicIsize = AtomicIsize::new(0);
+
+/// Over this, timed out jobs which are still rendering use too many threads
+/// already and new jobs with a timeout are refused.
+const MAX_ABANDONED_RENDERS: isize = 16;
+
+/// Runs `job` on its own thread and gives up waiting on it after `timeout`
+/// milliseconds, so the scheduler is released even if resvg is still busy.
+/// An abandoned job stops at its next `Cancel::check`, the pixmaps are
+/// rendered by bands of rows to check it in between.
+fn run_with_timeout<T, F>(timeout: Option<u64>, job: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce(&Cancel) -> Result<T, Error> + Send + 'static,
+{
+    let timeout = match timeout {
+        Some(timeout) => Duration::from_millis(timeout),
+        None => return job(&Cancel::never()),
+    };
+
+    if ABANDONED_RENDERS.load(Ordering::SeqCst) >= MAX_ABANDONED_RENDERS {
+        return Err(Error::Message(
+            "too many timed out renders are still running".to_string(),
+        ));
+    }
+
+    let cancel = Cancel::new();
+    let cancelled = cancel.0.clone().expect("a new Cancel is cancellable");
+    let done = Done(cancelled.clone());
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("resvg-render".to_string())
+        .spawn(move || {
+            let _done = done;
+            // The receiver is gone when the caller timed out.
+            let _ = sender.send(job(&cancel));
+        })
+        .map_err(|e| Error::Message(e.to_string()))?;
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            if !cancelled.swap(true, Ordering::SeqCst) {
+                ABANDONED_RENDERS.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(Error::Timeout)
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Err(Error::Message("render thread panicked".to_string()))
+        }
+    }
+}
+
+/// Flags a job as over when dropped, even by a panic. A job which was
+/// cancelled before was counted as abandoned.
+struct Done(Arc<AtomicBool>);
+
+impl Drop for Done {
+    fn drop(&mut self) {
+        if self.0.swap(true, Ordering::SeqCst) {
+            ABANDONED_RENDERS.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Reads a svg or svgz file, neither the file nor the inflated svg can be
+/// bigger than `max_input_bytes`.
+fn read_svg_file<P: AsRef<path::Path>>(in_svg: P, limits: &Limits) -> Result<Vec<u8>, Error> {
+    let file_error = |e: std::io::Error| format!("Error loading svg file: {}", e);
+
+    let file_size = std::fs::metadata(&in_svg).map_err(file_error)?.len();
+    check_limit(file_size, limits.max_input_bytes, atoms::max_input_bytes())?;
+
+    let svg_data = std::fs::read(&in_svg).map_err(file_error)?;
+
+    if svg_data.starts_with(&[0x1f, 0x8b]) {
+        return inflate(&svg_data, limits);
+    }
+
+    Ok(svg_data)
+}
+
+/// Inflates a svgz, stopping as soon as it exceeds `max_input_bytes`.
+fn inflate(svgz: &[u8], limits: &Limits) -> Result<Vec<u8>, Error> {
+    let max_size = limits
+        .max_input_bytes
+        .map_or(u64::MAX, |max| max.saturating_add(1));
+
+    let mut svg_data = Vec::new();
+    flate2::read::GzDecoder::new(svgz)
+        .take(max_size)
+        .read_to_end(&mut svg_data)
+        .map_err(|e| e.to_string())?;
+
+    check_limit(
+        svg_data.len() as u64,
+        limits.max_input_bytes,
+        atoms::max_input_bytes(),
+    )?;
+
+    Ok(svg_data)
+}
+
+/// Parses the XML of an svg within the size, DTD and element `limits`.
+fn parse_xml<'input>(
+    svg_string: &'input str,
+    limits: &Limits,
+) -> Result<usvg::roxmltree::Document<'input>, Error> {
+    check_limit(
+        svg_string.len() as u64,
+        limits.max_input_bytes,
+        atoms::max_input_bytes(),
+    )?;
+
+    let xml_opt = usvg::roxmltree::ParsingOptions {
+        allow_dtd: limits.allow_dtd,
+        ..Default::default()
+    };
+
+    let xml_tree = usvg::roxmltree::Document::parse_with_options(svg_string, xml_opt).map_err(
+        |e| match e {
+            usvg::roxmltree::Error::DtdDetected => Error::LimitExceeded(atoms::allow_dtd()),
+            e => Error::Message(e.to_string()),
+        },
+    )?;
+
+    // The nodes limit of roxmltree would count the text and comments too,
+    // the parsing is bounded by `max_input_bytes` already.
+    let elements = xml_tree.descendants().filter(|n| n.is_element()).count();
+    check_limit(elements as u64, limits.max_elements, atoms::max_elements())?;
+
+    Ok(xml_tree)
+}
+
+fn parse_svg(svg_string: &str, parsed_options: &mut ParsedOptions) -> Result<usvg::Tree, Error> {
+    let limits = parsed_options.limits;
+
+    let xml_tree = parse_xml(svg_string, &limits)?;
+
+    let rewritten = parsed_options
+        .rewrite
+        .apply(&xml_tree)
+        .map_err(|e| format!("Error patch: {}", e))?;
+    let xml_tree = match &rewritten {
+        Some(svg) => usvg::roxmltree::Document::parse(svg).map_err(|e| e.to_string())?,
+        None => xml_tree,
+    };
+
+    // fontdb initialization is pretty expensive, so perform it only when needed.
+    let has_text_nodes = xml_tree
+        .descendants()
+        .any(|n| n.has_tag_name(("http://www.w3.org/2000/svg", "text")));
+
+    if has_text_nodes && !parsed_options.fonts_loaded {
+        let font_properties = FontProperties::from_parsed_options(parsed_options);
+        load_fonts(&font_properties, parsed_options.usvg.fontdb_mut())?;
+    }
+
+    let tree =
+        usvg::Tree::from_xmltree(&xml_tree, &parsed_options.usvg).map_err(|e| e.to_string())?;
+
+    parsed_options.view_box = view_box_transform(xml_tree.root_element(), tree.size());
+
+    if let Some(error) = parsed_options.href_error.lock().unwrap().take() {
+        return Err(error);
+    }
+
+    // `use` elements are instantiated during conversion, so the tree can be
+    // much bigger than the XML it comes from.
+    if limits.max_elements.is_some() {
+        check_limit(
+            count_nodes(tree.root()) as u64,
+            limits.max_elements,
+            atoms::max_elements(),
+        )?;
+    }
+
+    Ok(tree)
+}
+
+fn parse_options<'a>(in_svg: InputFrom, options: Options) -> Result<ParsedOptions<'a>, String> {
+    let width = options.width.map(|w| w.to_px(options.dpi));
+    let height = options.height.map(|h| h.to_px(options.dpi));
+
+    // Physical units can round down to 0 pixels.
+    let target_size = |w: u32, h: u32| {
+        usvg::Size::from_wh(w as f32, h as f32).ok_or_else(|| "target size is zero".to_string())
+    };
+
+    let mut fit_to = FitTo::Original;
+    let mut default_size = target_size(100, 100)?;
+    if let (Some(w), Some(h)) = (width, height) {
+        default_size = target_size(w, h)?;
+        fit_to = FitTo::Size(w, h);
+    } else if let Some(w) = width {
+        default_size = target_size(w, 100)?;
+        fit_to = FitTo::Width(w);
+    } else if let Some(h) = height {
+        default_size = target_size(100, h)?;
+        fit_to = FitTo::Height(h);
+    } else if let Some(z) = options.zoom {
+        fit_to = FitTo::Zoom(z);
+    }
+
+    let resources_dir = match options.resources_dir {
+        Some(v) => Some(path::PathBuf::from(v)),
+        None => match in_svg {
+            InputFrom::File(ref path) => std::fs::canonicalize(path)
+                .ok()
+                .and_then(|p| p.parent().map(|p| p.to_path_buf())),
+
+            InputFrom::Text | InputFrom::Empty => None,
+        },
+    };
+
+    let href_error = Arc::new(Mutex::new(None));
 
     let usvg_options = usvg::Options {
         resources_dir,
@@ -484,18 +1376,21 @@ fn parse_options<'a>(in_svg: InputFrom, options: Options) -> Result<ParsedOption
         font_family: options
             .font_family
             .clone()
-            .take()
             .unwrap_or_else(|| "Times New Roman".to_string()),
         font_size: options.font_size as f32,
         languages: options.languages,
         shape_rendering: options.shape_rendering.get(),
         text_rendering: options.text_rendering.get(),
         image_rendering: options.image_rendering.get(),
-        default_size: default_size,
-        image_href_resolver: usvg::ImageHrefResolver::default(),
+        default_size,
+        image_href_resolver: image_href_resolver(
+            options.images,
+            options.limits,
+            href_error.clone(),
+        ),
         font_resolver: usvg::FontResolver::default(),
         fontdb: Arc::new(fontdb::Database::new()),
-        style_sheet: None,
+        style_sheet: options.stylesheet,
     };
 
     let background = match options.background {
@@ -506,22 +1401,59 @@ fn parse_options<'a>(in_svg: InputFrom, options: Options) -> Result<ParsedOption
         None => None,
     };
 
-    let font_files = options
-        .font_files
-        .iter()
-        .map(|s| path::PathBuf::from(s))
-        .collect();
+    if let Some(color_str) = &options.current_color {
+        if let Err(error) = color_str.parse::<svgtypes::Color>() {
+            return Err(format!("Error current_color: {}", error));
+        }
+    }
 
-    let font_dirs = options
-        .font_dirs
-        .iter()
-        .map(|s| path::PathBuf::from(s))
-        .collect();
+    let font_files = options.font_files.iter().map(path::PathBuf::from).collect();
+
+    let font_dirs = options.font_dirs.iter().map(path::PathBuf::from).collect();
 
     Ok(ParsedOptions {
         usvg: usvg_options,
+        fonts_loaded: false,
+        href_error,
         fit_to,
+        fit: options.fit,
+        align: options.align,
+        size_bounds: SizeBounds {
+            max_width: options.max_width,
+            max_height: options.max_height,
+            min_width: options.min_width,
+            min_height: options.min_height,
+        },
+        region: options.region,
+        view_box: tiny_skia::Transform::default(),
+        orientation: Orientation {
+            rotate: options.rotate,
+            flip: options.flip,
+        },
+        padding: options.padding,
+        color_transform: options.color_transform,
+        rewrite: rewrite::Rewrite {
+            current_color: options.current_color,
+            variables: options
+                .variables
+                .0
+                .into_iter()
+                .map(|(name, value)| (name.trim_start_matches("--").to_string(), value))
+                .collect(),
+            hide_ids: options.hide_ids,
+            show_only_ids: options.show_only_ids,
+            text: options.text.0,
+            patch: options.patch,
+        },
         background,
+        output: encoder::EncodeOptions {
+            dpi: options.dpi,
+            metadata: options.png_metadata.0,
+            encoding: options.png,
+            palette: options.palette,
+            monochrome: options.monochrome,
+        },
+        limits: options.limits,
         serif_family: options.serif_family,
         sans_serif_family: options.sans_serif_family,
         cursive_family: options.cursive_family,
@@ -533,6 +1465,106 @@ fn parse_options<'a>(in_svg: InputFrom, options: Options) -> Result<ParsedOption
     })
 }
 
+fn image_href_resolver<'a>(
+    images: HashMap<String, ImageDataWrapper>,
+    limits: Limits,
+    href_error: Arc<Mutex<Option<Error>>>,
+) -> usvg::ImageHrefResolver<'a> {
+    let images: HashMap<String, Arc<Vec<u8>>> = images
+        .into_iter()
+        .map(|(href, data)| (href, Arc::new(data.get())))
+        .collect();
+
+    // Only the first error is reported, the image is skipped.
+    let report = Arc::new(move |error: Error| {
+        href_error.lock().unwrap().get_or_insert(error);
+    });
+
+    // Nested svg images are parsed by usvg without any limit, they are
+    // checked here first.
+    let resolve_data = {
+        let report = report.clone();
+        move |mime: &str, data: Arc<Vec<u8>>, opts: &usvg::Options| match check_nested_svg(
+            mime, data, &limits,
+        ) {
+            Ok(data) => usvg::ImageHrefResolver::default_data_resolver()(mime, data, opts),
+            Err(error) => {
+                report(error);
+                None
+            }
+        }
+    };
+
+    // Preloaded images and files are sniffed by the data resolver, the same
+    // way a `data:` url without a mime type would be.
+    usvg::ImageHrefResolver {
+        resolve_data: Box::new(resolve_data.clone()),
+        resolve_string: Box::new(move |href, opts| {
+            if let Some(data) = images.get(href) {
+                return resolve_data("text/plain", data.clone(), opts);
+            }
+
+            // Without `resources_dir` a relative path would silently resolve
+            // against the BEAM working directory, report it instead.
+            let is_relative_path = !href.contains("://") && path::Path::new(href).is_relative();
+            if opts.resources_dir.is_none() && is_relative_path {
+                report(Error::Message(format!(
+                    "Make sure to set resources_dir to resolve the relative path '{}'",
+                    href
+                )));
+                return None;
+            }
+
+            // Like the default string resolver, urls are ignored.
+            let path = opts.get_abs_path(path::Path::new(href));
+            let data = std::fs::read(path).ok()?;
+            resolve_data("text/plain", Arc::new(data), opts)
+        }),
+    }
+}
+
+/// Checks a nested svg image against `limits`, raster images are left as
+/// is. A svgz is returned inflated.
+fn check_nested_svg(
+    mime: &str,
+    data: Arc<Vec<u8>>,
+    limits: &Limits,
+) -> Result<Arc<Vec<u8>>, Error> {
+    if mime != "image/svg+xml" && (mime != "text/plain" || is_raster_image(&data)) {
+        return Ok(data);
+    }
+
+    let data = if data.starts_with(&[0x1f, 0x8b]) {
+        Arc::new(inflate(&data, limits)?)
+    } else {
+        data
+    };
+
+    // Not an svg when it isn't text, usvg skips it.
+    if let Ok(svg_string) = std::str::from_utf8(&data) {
+        parse_xml(svg_string, limits)?;
+    }
+
+    Ok(data)
+}
+
+fn is_raster_image(data: &[u8]) -> bool {
+    data.starts_with(b"\x89PNG")
+        || data.starts_with(&[0xff, 0xd8, 0xff])
+        || data.starts_with(b"GIF8")
+        || (data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]))
+}
+
+fn load_fontdb(options: Options) -> Result<fontdb::Database, String> {
+    let parsed_options = parse_options(InputFrom::Empty, options)?;
+    let font_properties = FontProperties::from_parsed_options(&parsed_options);
+
+    let mut fontdb = fontdb::Database::new();
+    load_fonts(&font_properties, &mut fontdb)?;
+
+    Ok(fontdb)
+}
+
 fn load_fonts(
     font_properties: &FontProperties,
     fontdb: &mut fontdb::Database,
@@ -673,33 +1705,921 @@ impl ImageRenderingWrapper {
     }
 }
 
+impl<'a> Decoder<'a> for ImageDataWrapper {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let binary: Binary = term.decode()?;
+        Ok(Self {
+            value: binary.as_slice().to_vec(),
+        })
+    }
+}
+
+impl Encoder for ImageDataWrapper {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let mut binary = OwnedBinary::new(self.value.len()).unwrap();
+        binary.as_mut_slice().copy_from_slice(&self.value);
+        binary.release(env).encode(env)
+    }
+}
+
+impl ImageDataWrapper {
+    fn get(self) -> Vec<u8> {
+        self.value
+    }
+}
+
+impl<'a> Decoder<'a> for PngMetadata {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let mut metadata = Vec::new();
+        for (key, text) in term.decode::<Vec<(Term, String)>>()? {
+            let keyword = match key.decode::<String>() {
+                Ok(keyword) => keyword,
+                // `:creation_time` is the "Creation Time" keyword.
+                Err(_) => key
+                    .atom_to_string()?
+                    .split('_')
+                    .map(|word| {
+                        let mut chars = word.chars();
+                        match chars.next() {
+                            Some(first) => first.to_uppercase().chain(chars).collect(),
+                            None => String::new(),
+                        }
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            };
+            metadata.push((keyword, text));
+        }
+        Ok(PngMetadata(metadata))
+    }
+}
+
+impl Encoder for PngMetadata {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        self.0.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for StringMap {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let mut map = HashMap::new();
+        for (key, value) in
+            rustler::types::map::MapIterator::new(term).ok_or(rustler::Error::BadArg)?
+        {
+            let key = match key.decode::<String>() {
+                Ok(key) => key,
+                Err(_) => key.atom_to_string()?,
+            };
+            map.insert(key, value.decode()?);
+        }
+        Ok(StringMap(map))
+    }
+}
+
+impl Encoder for StringMap {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        self.0.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for rewrite::Patch {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let (target, attribute, value): (String, Term, Term) = term.decode()?;
+
+        // `:stroke_width` is the `stroke-width` attribute.
+        let attribute = match attribute.decode::<String>() {
+            Ok(attribute) => attribute,
+            Err(_) => attribute.atom_to_string()?.replace('_', "-"),
+        };
+
+        let value = if let Ok(value) = value.decode::<Option<String>>() {
+            value
+        } else if let Ok(value) = value.decode::<i64>() {
+            Some(value.to_string())
+        } else {
+            Some(value.decode::<f64>()?.to_string())
+        };
+
+        Ok(rewrite::Patch {
+            target,
+            attribute,
+            value,
+        })
+    }
+}
+
+impl Encoder for rewrite::Patch {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        (&self.target, &self.attribute, &self.value).encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for encoder::Encoding {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let mut encoding = encoder::Encoding::default();
+        for (key, value) in term.decode::<Vec<(Atom, Term)>>()? {
+            if key == atoms::compression() {
+                encoding.compression = value.decode()?;
+            } else if key == atoms::filter() {
+                encoding.filter = value.decode()?;
+            } else if key == atoms::reduce() {
+                encoding.reduce = value.decode()?;
+            } else {
+                return Err(rustler::Error::BadArg);
+            }
+        }
+        Ok(encoding)
+    }
+}
+
+impl Encoder for encoder::Encoding {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        vec![
+            (atoms::compression(), self.compression.encode(env)),
+            (atoms::filter(), self.filter.encode(env)),
+            (atoms::reduce(), self.reduce.encode(env)),
+        ]
+        .encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for encoder::Palette {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let mut palette = encoder::Palette::default();
+        for (key, value) in term.decode::<Vec<(Atom, Term)>>()? {
+            if key == atoms::colors() {
+                palette.colors = value.decode()?;
+            } else if key == atoms::dither() {
+                palette.dither = value.decode()?;
+            } else if key == atoms::format() {
+                let format = value.decode::<Atom>()?;
+                palette.format = if format == atoms::png() {
+                    encoder::PaletteFormat::Png
+                } else if format == atoms::gif() {
+                    encoder::PaletteFormat::Gif
+                } else {
+                    return Err(rustler::Error::BadArg);
+                };
+            } else {
+                return Err(rustler::Error::BadArg);
+            }
+        }
+
+        if !(2..=256).contains(&palette.colors) {
+            return Err(rustler::Error::BadArg);
+        }
+        Ok(palette)
+    }
+}
+
+impl Encoder for encoder::Palette {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let format = match self.format {
+            encoder::PaletteFormat::Png => atoms::png(),
+            encoder::PaletteFormat::Gif => atoms::gif(),
+        };
+        vec![
+            (atoms::colors(), self.colors.encode(env)),
+            (atoms::dither(), self.dither.encode(env)),
+            (atoms::format(), format.encode(env)),
+        ]
+        .encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for monochrome::Monochrome {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let mut mono = monochrome::Monochrome::default();
+        for (key, value) in term.decode::<Vec<(Atom, Term)>>()? {
+            if key == atoms::format() {
+                mono.format = value.decode()?;
+            } else if key == atoms::threshold() {
+                mono.threshold = value.decode()?;
+            } else if key == atoms::dither() {
+                mono.dither = value.decode()?;
+            } else {
+                return Err(rustler::Error::BadArg);
+            }
+        }
+        Ok(mono)
+    }
+}
+
+impl Encoder for monochrome::Monochrome {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        vec![
+            (atoms::format(), self.format.encode(env)),
+            (atoms::threshold(), self.threshold.encode(env)),
+            (atoms::dither(), self.dither.encode(env)),
+        ]
+        .encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for monochrome::MonochromeFormat {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let atom = term.atom_to_string()?;
+        let value = match atom.as_str() {
+            "png" => monochrome::MonochromeFormat::Png,
+            "pbm" => monochrome::MonochromeFormat::Pbm,
+            "bits" => monochrome::MonochromeFormat::Bits,
+            "escpos" => monochrome::MonochromeFormat::EscPos,
+            "zpl" => monochrome::MonochromeFormat::Zpl,
+            _ => return Err(rustler::Error::BadArg),
+        };
+        Ok(value)
+    }
+}
+
+impl Encoder for monochrome::MonochromeFormat {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let atom = match self {
+            monochrome::MonochromeFormat::Png => atoms::png(),
+            monochrome::MonochromeFormat::Pbm => atoms::pbm(),
+            monochrome::MonochromeFormat::Bits => atoms::bits(),
+            monochrome::MonochromeFormat::EscPos => atoms::escpos(),
+            monochrome::MonochromeFormat::Zpl => atoms::zpl(),
+        };
+        atom.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for encoder::Compression {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let atom = term.atom_to_string()?;
+        let value = match atom.as_str() {
+            "fast" => encoder::Compression::Fast,
+            "default" => encoder::Compression::Default,
+            "best" => encoder::Compression::Best,
+            _ => return Err(rustler::Error::BadArg),
+        };
+        Ok(value)
+    }
+}
+
+impl Encoder for encoder::Compression {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let atom = match self {
+            encoder::Compression::Fast => atoms::fast(),
+            encoder::Compression::Default => atoms::default(),
+            encoder::Compression::Best => atoms::best(),
+        };
+        atom.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for encoder::Filter {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let atom = term.atom_to_string()?;
+        let value = match atom.as_str() {
+            "none" => encoder::Filter::None,
+            "sub" => encoder::Filter::Sub,
+            "up" => encoder::Filter::Up,
+            "avg" => encoder::Filter::Avg,
+            "paeth" => encoder::Filter::Paeth,
+            "adaptive" => encoder::Filter::Adaptive,
+            _ => return Err(rustler::Error::BadArg),
+        };
+        Ok(value)
+    }
+}
+
+impl Encoder for encoder::Filter {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let atom = match self {
+            encoder::Filter::None => atoms::none(),
+            encoder::Filter::Sub => atoms::sub(),
+            encoder::Filter::Up => atoms::up(),
+            encoder::Filter::Avg => atoms::avg(),
+            encoder::Filter::Paeth => atoms::paeth(),
+            encoder::Filter::Adaptive => atoms::adaptive(),
+        };
+        atom.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for FitTo {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        if let Ok(w) = term.decode::<u32>() {
+            return Ok(FitTo::Width(w));
+        }
+
+        if let Ok((w, h)) = term.decode::<(u32, u32)>() {
+            return Ok(FitTo::Size(w, h));
+        }
+
+        let (key, value) = term.decode::<(Atom, Term)>()?;
+        if key == atoms::width() {
+            Ok(FitTo::Width(value.decode()?))
+        } else if key == atoms::height() {
+            Ok(FitTo::Height(value.decode()?))
+        } else if key == atoms::zoom() {
+            Ok(FitTo::Zoom(value.decode()?))
+        } else {
+            Err(rustler::Error::BadArg)
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for FitMode {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let atom = term.atom_to_string()?;
+        let value = match atom.as_str() {
+            "contain" => FitMode::Contain,
+            "cover" => FitMode::Cover,
+            "fill" => FitMode::Fill,
+            _ => return Err(rustler::Error::BadArg),
+        };
+        Ok(value)
+    }
+}
+
+impl Encoder for FitMode {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let atom = match self {
+            FitMode::Contain => atoms::contain(),
+            FitMode::Cover => atoms::cover(),
+            FitMode::Fill => atoms::fill(),
+        };
+        atom.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for Align {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let atom = term.atom_to_string()?;
+        let value = match atom.as_str() {
+            "top_left" => Align::TopLeft,
+            "top" => Align::Top,
+            "top_right" => Align::TopRight,
+            "left" => Align::Left,
+            "center" => Align::Center,
+            "right" => Align::Right,
+            "bottom_left" => Align::BottomLeft,
+            "bottom" => Align::Bottom,
+            "bottom_right" => Align::BottomRight,
+            _ => return Err(rustler::Error::BadArg),
+        };
+        Ok(value)
+    }
+}
+
+impl Encoder for Align {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let atom = match self {
+            Align::TopLeft => atoms::top_left(),
+            Align::Top => atoms::top(),
+            Align::TopRight => atoms::top_right(),
+            Align::Left => atoms::left(),
+            Align::Center => atoms::center(),
+            Align::Right => atoms::right(),
+            Align::BottomLeft => atoms::bottom_left(),
+            Align::Bottom => atoms::bottom(),
+            Align::BottomRight => atoms::bottom_right(),
+        };
+        atom.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for ColorTransform {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        if let Ok(values) = term.decode::<Vec<Term>>() {
+            let mut matrix = [0.0; 20];
+            if values.len() != matrix.len() {
+                return Err(rustler::Error::BadArg);
+            }
+            for (m, value) in matrix.iter_mut().zip(values) {
+                *m = decode_number(value)?;
+            }
+            return Ok(ColorTransform::Matrix(matrix));
+        }
+
+        let atom = term.atom_to_string()?;
+        let value = match atom.as_str() {
+            "grayscale" => ColorTransform::Grayscale,
+            "sepia" => ColorTransform::Sepia,
+            "invert" => ColorTransform::Invert,
+            _ => return Err(rustler::Error::BadArg),
+        };
+        Ok(value)
+    }
+}
+
+impl Encoder for ColorTransform {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            ColorTransform::Grayscale => atoms::grayscale().encode(env),
+            ColorTransform::Sepia => atoms::sepia().encode(env),
+            ColorTransform::Invert => atoms::invert().encode(env),
+            ColorTransform::Matrix(m) => m.to_vec().encode(env),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for Dimension {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        if let Ok(px) = term.decode::<u32>() {
+            return Ok(Dimension::Px(px));
+        }
+
+        let (value, unit) = term.decode::<(Term, Atom)>()?;
+        let value = decode_number(value)?;
+        if unit == atoms::mm() {
+            Ok(Dimension::Mm(value))
+        } else if unit == atoms::cm() {
+            Ok(Dimension::Cm(value))
+        } else if unit == atoms::inch() {
+            Ok(Dimension::In(value))
+        } else if unit == atoms::pt() {
+            Ok(Dimension::Pt(value))
+        } else {
+            Err(rustler::Error::BadArg)
+        }
+    }
+}
+
+impl Encoder for Dimension {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match *self {
+            Dimension::Px(px) => px.encode(env),
+            Dimension::Mm(v) => (v, atoms::mm()).encode(env),
+            Dimension::Cm(v) => (v, atoms::cm()).encode(env),
+            Dimension::In(v) => (v, atoms::inch()).encode(env),
+            Dimension::Pt(v) => (v, atoms::pt()).encode(env),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for Region {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let (x, y, w, h) = term.decode::<(Term, Term, Term, Term)>()?;
+        tiny_skia::NonZeroRect::from_xywh(
+            decode_number(x)?,
+            decode_number(y)?,
+            decode_number(w)?,
+            decode_number(h)?,
+        )
+        .map(Region)
+        .ok_or(rustler::Error::BadArg)
+    }
+}
+
+impl Encoder for Region {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        (self.0.x(), self.0.y(), self.0.width(), self.0.height()).encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for Angle {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        decode_number(term).map(Angle)
+    }
+}
+
+impl Encoder for Angle {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        self.0.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for Flip {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let atom = term.atom_to_string()?;
+        let value = match atom.as_str() {
+            "horizontal" => Flip::Horizontal,
+            "vertical" => Flip::Vertical,
+            _ => return Err(rustler::Error::BadArg),
+        };
+        Ok(value)
+    }
+}
+
+impl Encoder for Flip {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let atom = match self {
+            Flip::Horizontal => atoms::horizontal(),
+            Flip::Vertical => atoms::vertical(),
+        };
+        atom.encode(env)
+    }
+}
+
+impl<'a> Decoder<'a> for Length {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        if let Ok(value) = decode_number(term) {
+            return Ok(Length::Px(value));
+        }
+
+        let (value, unit) = term.decode::<(Term, Atom)>()?;
+        if unit == atoms::percent() {
+            Ok(Length::Percent(decode_number(value)?))
+        } else {
+            Err(rustler::Error::BadArg)
+        }
+    }
+}
+
+impl Encoder for Length {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match *self {
+            Length::Px(v) => v.encode(env),
+            Length::Percent(p) => (p, atoms::percent()).encode(env),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for Padding {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        if let Ok((top, right, bottom, left)) = term.decode::<(Length, Length, Length, Length)>() {
+            return Ok(Padding {
+                top,
+                right,
+                bottom,
+                left,
+            });
+        }
+
+        let length = term.decode::<Length>()?;
+        Ok(Padding {
+            top: length,
+            right: length,
+            bottom: length,
+            left: length,
+        })
+    }
+}
+
+impl Encoder for Padding {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        (self.top, self.right, self.bottom, self.left).encode(env)
+    }
+}
+
+/// Decodes an integer or a float.
+fn decode_number(term: Term) -> rustler::NifResult<f32> {
+    match term.decode::<i64>() {
+        Ok(v) => Ok(v as f32),
+        Err(_) => term.decode::<f32>(),
+    }
+}
+
+impl<'a> Decoder<'a> for Limits {
+    fn decode(term: Term<'a>) -> rustler::NifResult<Self> {
+        let mut limits = Limits::default();
+        for (key, value) in term.decode::<Vec<(Atom, Term)>>()? {
+            if key == atoms::max_input_bytes() {
+                limits.max_input_bytes = value.decode()?;
+            } else if key == atoms::max_elements() {
+                limits.max_elements = value.decode()?;
+            } else if key == atoms::max_pixels() {
+                limits.max_pixels = value.decode()?;
+            } else if key == atoms::max_filter_area() {
+                limits.max_filter_area = value.decode()?;
+            } else if key == atoms::allow_dtd() {
+                limits.allow_dtd = value.decode()?;
+            } else {
+                return Err(rustler::Error::BadArg);
+            }
+        }
+        Ok(limits)
+    }
+}
+
+impl Encoder for Limits {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        vec![
+            (atoms::max_input_bytes(), self.max_input_bytes.encode(env)),
+            (atoms::max_elements(), self.max_elements.encode(env)),
+            (atoms::max_pixels(), self.max_pixels.encode(env)),
+            (atoms::max_filter_area(), self.max_filter_area.encode(env)),
+            (atoms::allow_dtd(), self.allow_dtd.encode(env)),
+        ]
+        .encode(env)
+    }
+}
+
 fn render_svg(
     parsed_options: &ParsedOptions,
     tree: &usvg::Tree,
-) -> Result<tiny_skia::Pixmap, String> {
-    let img = {
-        let size = parsed_options
-            .fit_to
-            .fit_to_size(tree.size().to_int_size())
-            .ok_or_else(|| "target size is zero".to_string())?;
+    cancel: &Cancel,
+) -> Result<tiny_skia::Pixmap, Error> {
+    render_svg_with_fit(parsed_options, parsed_options.fit_to, tree, cancel)
+}
+
+fn render_svg_with_fit(
+    parsed_options: &ParsedOptions,
+    fit_to: FitTo,
+    tree: &usvg::Tree,
+    cancel: &Cancel,
+) -> Result<tiny_skia::Pixmap, Error> {
+    let (size, transform) = output_size_and_transform(parsed_options, fit_to, tree)?;
+
+    render_pixmap(parsed_options, tree, size, transform, cancel)
+}
+
+/// Output size and transform from the size, region, orientation and padding
+/// options. The output limits are checked here.
+fn output_size_and_transform(
+    parsed_options: &ParsedOptions,
+    fit_to: FitTo,
+    tree: &usvg::Tree,
+) -> Result<(tiny_skia::IntSize, tiny_skia::Transform), Error> {
+    let limits = parsed_options.limits;
+
+    let region = match parsed_options.region {
+        Some(region) => Some(
+            region
+                .0
+                .transform(parsed_options.view_box)
+                .ok_or_else(|| "region is empty".to_string())?,
+        ),
+        None => None,
+    };
+
+    let (size, transform) = fit_to
+        .fit_to_size_and_transform(
+            region.map_or(tree.size(), |r| r.size()).to_int_size(),
+            parsed_options.fit,
+            parsed_options.align,
+        )
+        .ok_or_else(|| "target size is zero".to_string())?;
+
+    let transform = match region {
+        Some(r) => transform.pre_translate(-r.x(), -r.y()),
+        None => transform,
+    };
+
+    let (size, transform) = parsed_options
+        .size_bounds
+        .bound(size, transform)
+        .ok_or_else(|| "target size is zero".to_string())?;
+
+    let (size, transform) = parsed_options
+        .orientation
+        .orient(size, transform)
+        .ok_or_else(|| "target size is too large".to_string())?;
+
+    let (size, transform) = match parsed_options.padding {
+        Some(padding) => padding
+            .pad(size, transform)
+            .ok_or_else(|| "target size is too large".to_string())?,
+        None => (size, transform),
+    };
+
+    check_limit(
+        size.width() as u64 * size.height() as u64,
+        limits.max_pixels,
+        atoms::max_pixels(),
+    )?;
+
+    if let Some(max_filter_area) = limits.max_filter_area {
+        check_filter_area(tree.root(), transform, max_filter_area)?;
+    }
+
+    Ok((size, transform))
+}
 
-        // Unwrap is safe, because `size` is already valid.
-        let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height()).unwrap();
+/// Scales `size` and `transform` down into a `side` square, centered. The
+/// padding and rotation enlarge the canvas of an icon, they are applied
+/// inside its square.
+fn fit_in_square(
+    size: tiny_skia::IntSize,
+    transform: tiny_skia::Transform,
+    side: u32,
+) -> Result<(tiny_skia::IntSize, tiny_skia::Transform), Error> {
+    let square =
+        tiny_skia::IntSize::from_wh(side, side).ok_or_else(|| "target size is zero".to_string())?;
+
+    let (w, h) = (size.width() as f32, size.height() as f32);
+    let side = side as f32;
+    let scale = (side / w.max(h)).min(1.0);
+    let transform = transform
+        .post_scale(scale, scale)
+        .post_translate((side - w * scale) / 2.0, (side - h * scale) / 2.0);
+
+    Ok((square, transform))
+}
+
+/// The transform usvg applies to the drawing for the root `viewBox`, not
+/// exposed by the tree.
+fn view_box_transform(svg: usvg::roxmltree::Node, size: usvg::Size) -> tiny_skia::Transform {
+    let view_box = svg
+        .attribute("viewBox")
+        .and_then(|v| v.parse::<svgtypes::ViewBox>().ok())
+        .and_then(|v| {
+            tiny_skia::NonZeroRect::from_xywh(v.x as f32, v.y as f32, v.w as f32, v.h as f32)
+        });
+
+    let view_box = match view_box {
+        Some(view_box) => view_box,
+        None => return tiny_skia::Transform::default(),
+    };
+
+    let aspect: svgtypes::AspectRatio = svg
+        .attribute("preserveAspectRatio")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default();
+
+    let sx = size.width() / view_box.width();
+    let sy = size.height() / view_box.height();
+    let (sx, sy) = match aspect.align {
+        svgtypes::Align::None => (sx, sy),
+        _ if aspect.slice => (sx.max(sy), sx.max(sy)),
+        _ => (sx.min(sy), sx.min(sy)),
+    };
+
+    // Space left around the scaled view box, split following the alignment.
+    let w = size.width() - view_box.width() * sx;
+    let h = size.height() - view_box.height() * sy;
+    let (dx, dy) = match aspect.align {
+        svgtypes::Align::None | svgtypes::Align::XMinYMin => (0.0, 0.0),
+        svgtypes::Align::XMidYMin => (w / 2.0, 0.0),
+        svgtypes::Align::XMaxYMin => (w, 0.0),
+        svgtypes::Align::XMinYMid => (0.0, h / 2.0),
+        svgtypes::Align::XMidYMid => (w / 2.0, h / 2.0),
+        svgtypes::Align::XMaxYMid => (w, h / 2.0),
+        svgtypes::Align::XMinYMax => (0.0, h),
+        svgtypes::Align::XMidYMax => (w / 2.0, h),
+        svgtypes::Align::XMaxYMax => (w, h),
+    };
+
+    tiny_skia::Transform::from_row(
+        sx,
+        0.0,
+        0.0,
+        sy,
+        dx - view_box.x() * sx,
+        dy - view_box.y() * sy,
+    )
+}
+
+/// Rows rendered between two `Cancel::check`, at least.
+const BAND_HEIGHT: u32 = 256;
+
+/// Bands of a cancellable render, at most. Each band renders the whole
+/// tree again, clipped to it.
+const MAX_BANDS: u32 = 16;
+
+/// Renders `tree` with `transform` into a new pixmap of `size`.
+fn render_pixmap(
+    parsed_options: &ParsedOptions,
+    tree: &usvg::Tree,
+    size: tiny_skia::IntSize,
+    transform: tiny_skia::Transform,
+    cancel: &Cancel,
+) -> Result<tiny_skia::Pixmap, Error> {
+    let new_pixmap = |width, height| {
+        let mut pixmap = tiny_skia::Pixmap::new(width, height)
+            .ok_or_else(|| "target size is too large".to_string())?;
 
         if let Some(background) = parsed_options.background {
             pixmap.fill(svg_to_skia_color(background));
         }
 
-        let transform = parsed_options
-            .fit_to
-            .fit_to_transform(tree.size().to_int_size());
+        Ok::<_, Error>(pixmap)
+    };
 
+    let mut pixmap = new_pixmap(size.width(), size.height())?;
+
+    // resvg clips the filter regions to twice the pixmap size around it, a
+    // band would lose the blur or offset coming from farther.
+    let band_height = if cancel.is_cancellable() && tree.filters().is_empty() {
+        BAND_HEIGHT.max(size.height().div_ceil(MAX_BANDS))
+    } else {
+        size.height()
+    };
+
+    if band_height >= size.height() {
         resvg::render(tree, transform, &mut pixmap.as_mut());
+    } else {
+        // Like the tiles, a band is rendered on its own with an offset.
+        let row_bytes = size.width() as usize * 4;
+        for y in (0..size.height()).step_by(band_height as usize) {
+            cancel.check()?;
+
+            let mut band = new_pixmap(size.width(), band_height.min(size.height() - y))?;
+            let band_transform = transform.post_translate(0.0, -(y as f32));
+            resvg::render(tree, band_transform, &mut band.as_mut());
+
+            let start = y as usize * row_bytes;
+            pixmap.data_mut()[start..start + band.data().len()].copy_from_slice(band.data());
+        }
+    }
+
+    if let Some(color_transform) = parsed_options.color_transform {
+        color_transform.apply(&mut pixmap);
+    }
+
+    Ok(pixmap)
+}
+
+fn check_limit(value: u64, limit: Option<u64>, name: Atom) -> Result<(), Error> {
+    match limit {
+        Some(limit) if value > limit => Err(Error::LimitExceeded(name)),
+        _ => Ok(()),
+    }
+}
+
+fn count_nodes(group: &usvg::Group) -> usize {
+    let mut count = 0;
+
+    for node in group.children() {
+        count += 1;
 
-        pixmap
+        if let usvg::Node::Group(ref group) = node {
+            count += count_nodes(group);
+        }
+
+        node.subroots(|subroot| count += count_nodes(subroot));
+    }
+
+    count
+}
+
+fn check_filter_area(
+    group: &usvg::Group,
+    transform: tiny_skia::Transform,
+    max_filter_area: u64,
+) -> Result<(), Error> {
+    if let Some(region) = group.filters_bounding_box() {
+        let area = region
+            .transform(group.abs_transform().post_concat(transform))
+            .map(|r| r.width() as f64 * r.height() as f64)
+            .unwrap_or(0.0);
+
+        if area > max_filter_area as f64 {
+            return Err(Error::LimitExceeded(atoms::max_filter_area()));
+        }
+    }
+
+    for node in group.children() {
+        if let usvg::Node::Group(ref group) = node {
+            check_filter_area(group, transform, max_filter_area)?;
+        }
+
+        let mut result = Ok(());
+        node.subroots(|subroot| {
+            if result.is_ok() {
+                result = check_filter_area(subroot, transform, max_filter_area);
+            }
+        });
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Union of the filter regions of `group` and its descendants, mapped by
+/// `transform`.
+fn filter_bounds(group: &usvg::Group, transform: tiny_skia::Transform) -> Option<tiny_skia::Rect> {
+    let mut bounds = group
+        .filters_bounding_box()
+        .and_then(|region| region.transform(group.abs_transform().post_concat(transform)))
+        .map(|region| region.to_rect());
+
+    let mut join = |other: Option<tiny_skia::Rect>| {
+        bounds = match (bounds, other) {
+            (Some(a), Some(b)) => tiny_skia::Rect::from_ltrb(
+                a.left().min(b.left()),
+                a.top().min(b.top()),
+                a.right().max(b.right()),
+                a.bottom().max(b.bottom()),
+            ),
+            (a, b) => a.or(b),
+        };
+    };
+
+    for node in group.children() {
+        if let usvg::Node::Group(ref group) = node {
+            join(filter_bounds(group, transform));
+        }
+
+        node.subroots(|subroot| join(filter_bounds(subroot, transform)));
+    }
+
+    bounds
+}
+
+/// Margin added around a `width` x `height` tile so `filters` are rendered
+/// whole. resvg clips the layers to twice the pixmap size around it, from
+/// `-2 * width - 5 * margin` to `3 * width + 5 * margin` in the tile pixels.
+fn tile_margin(filters: Option<tiny_skia::Rect>, width: u32, height: u32) -> u32 {
+    let filters = match filters {
+        Some(filters) => filters,
+        None => return 0,
     };
 
-    Ok(img)
+    let (w, h) = (width as f32, height as f32);
+    let overflow = [
+        -filters.left() - 2.0 * w,
+        filters.right() - 3.0 * w,
+        -filters.top() - 2.0 * h,
+        filters.bottom() - 3.0 * h,
+    ]
+    .iter()
+    .fold(0.0f32, |overflow, v| overflow.max(*v));
+
+    (overflow / 5.0).ceil() as u32
 }
 
 fn svg_to_skia_color(color: svgtypes::Color) -> tiny_skia::Color {
@@ -712,6 +2632,11 @@ rustler::init!(
         svg_to_png,
         svg_string_to_png,
         svg_string_to_png_buffer,
+        render_async,
+        render_sizes,
+        render_icon,
+        render_batch,
+        render_tiles,
         list_fonts,
         query_all
     ]
@@ -0,0 +1,114 @@
+// 1-bit output for thermal printers and e-ink displays.
+
+use std::convert::TryFrom;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Monochrome {
+    pub format: MonochromeFormat,
+    /// Pixels darker than this luminance are black.
+    pub threshold: u8,
+    /// Spread the error with Floyd–Steinberg instead of a plain threshold.
+    pub dither: bool,
+}
+
+impl Default for Monochrome {
+    fn default() -> Self {
+        Self {
+            format: MonochromeFormat::Png,
+            threshold: 128,
+            dither: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MonochromeFormat {
+    /// 1-bit grayscale png.
+    Png,
+    /// Binary portable bitmap, `P4`.
+    Pbm,
+    /// Only the packed rows.
+    Bits,
+    /// ESC/POS `GS v 0` raster bit image command.
+    EscPos,
+    /// ZPL label with a `^GFA` graphic field.
+    Zpl,
+}
+
+/// Rows packed 8 pixels per byte, most significant bit first, each row
+/// padded to a whole byte. 1 is black, transparent pixels are white paper.
+pub fn bilevel(rgba: &[u8], width: u32, threshold: u8, dither: bool) -> Vec<u8> {
+    let width = width as usize;
+    let row_bytes = width.div_ceil(8);
+    let height = rgba.len() / 4 / width.max(1);
+
+    let mut luma: Vec<f32> = rgba
+        .chunks_exact(4)
+        .map(|p| {
+            let y = 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+            let alpha = p[3] as f32 / 255.0;
+            y * alpha + 255.0 * (1.0 - alpha)
+        })
+        .collect();
+
+    let mut bits = vec![0u8; row_bytes * height];
+    for y in 0..height {
+        for x in 0..width {
+            let at = y * width + x;
+            let black = luma[at] < threshold as f32;
+            if black {
+                bits[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
+            }
+
+            if dither {
+                let error = luma[at] - if black { 0.0 } else { 255.0 };
+                if x + 1 < width {
+                    luma[at + 1] += error * 7.0 / 16.0;
+                }
+                if y + 1 < height {
+                    if x > 0 {
+                        luma[at + width - 1] += error * 3.0 / 16.0;
+                    }
+                    luma[at + width] += error * 5.0 / 16.0;
+                    if x + 1 < width {
+                        luma[at + width + 1] += error / 16.0;
+                    }
+                }
+            }
+        }
+    }
+
+    bits
+}
+
+pub fn pbm(bits: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut data = format!("P4\n{} {}\n", width, height).into_bytes();
+    data.extend_from_slice(bits);
+    data
+}
+
+pub fn escpos(bits: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let row_bytes =
+        u16::try_from(width.div_ceil(8)).map_err(|_| "escpos width is too large".to_string())?;
+    let height = u16::try_from(height).map_err(|_| "escpos height is too large".to_string())?;
+
+    // GS v 0, normal density.
+    let mut data = vec![0x1d, 0x76, 0x30, 0x00];
+    data.extend_from_slice(&row_bytes.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.extend_from_slice(bits);
+    Ok(data)
+}
+
+pub fn zpl(bits: &[u8], width: u32) -> Vec<u8> {
+    let row_bytes = width.div_ceil(8);
+    let hex: String = bits.iter().map(|b| format!("{:02X}", b)).collect();
+
+    format!(
+        "^XA^FO0,0^GFA,{total},{total},{row},{hex}^FS^XZ",
+        total = bits.len(),
+        row = row_bytes,
+        hex = hex
+    )
+    .into_bytes()
+}
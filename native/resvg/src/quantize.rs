@@ -0,0 +1,203 @@
+// Color quantization of straight alpha RGBA pixels to a small palette.
+
+use std::collections::{HashMap, HashSet};
+
+/// Pixels as indices in a palette of at most 256 colors.
+pub struct Quantized {
+    pub palette: Vec<[u8; 4]>,
+    pub indices: Vec<u8>,
+}
+
+/// Reduces `rgba` to at most `colors` colors. The palette is kept exact when
+/// the image has few enough colors, otherwise it is computed by median cut.
+/// `dither` spreads the error with Floyd–Steinberg.
+pub fn quantize(rgba: &[u8], width: u32, colors: usize, dither: bool) -> Quantized {
+    let colors = colors.clamp(2, 256);
+
+    if let Some(palette) = exact_palette(rgba, colors) {
+        let index: HashMap<[u8; 4], u8> = palette
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (*c, i as u8))
+            .collect();
+
+        let indices = rgba
+            .chunks_exact(4)
+            .map(|p| index[&[p[0], p[1], p[2], p[3]]])
+            .collect();
+
+        return Quantized { palette, indices };
+    }
+
+    let palette = median_cut(rgba, colors);
+
+    let indices = if dither {
+        dither_indices(rgba, width as usize, &palette)
+    } else {
+        let mut nearest = Nearest::new(&palette);
+        rgba.chunks_exact(4)
+            .map(|p| nearest.index([p[0], p[1], p[2], p[3]]))
+            .collect()
+    };
+
+    Quantized { palette, indices }
+}
+
+fn exact_palette(rgba: &[u8], max: usize) -> Option<Vec<[u8; 4]>> {
+    let mut seen = HashSet::new();
+    for p in rgba.chunks_exact(4) {
+        if seen.insert([p[0], p[1], p[2], p[3]]) && seen.len() > max {
+            return None;
+        }
+    }
+
+    let mut palette: Vec<[u8; 4]> = seen.into_iter().collect();
+    palette.sort_unstable();
+    Some(palette)
+}
+
+/// Splits the color histogram in `colors` boxes, each box is one palette
+/// color: the average of its pixels.
+fn median_cut(rgba: &[u8], colors: usize) -> Vec<[u8; 4]> {
+    let mut histogram: HashMap<[u8; 4], u32> = HashMap::new();
+    for p in rgba.chunks_exact(4) {
+        *histogram.entry([p[0], p[1], p[2], p[3]]).or_insert(0) += 1;
+    }
+
+    let mut boxes = vec![histogram.into_iter().collect::<Vec<_>>()];
+
+    while boxes.len() < colors {
+        // Split the box with the widest channel range.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let (channel, range) = widest_channel(b);
+                (i, channel, range)
+            })
+            .max_by_key(|(_, _, range)| *range);
+
+        let (i, channel) = match widest {
+            Some((i, channel, _)) => (i, channel),
+            None => break,
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|(c, _)| c[channel]);
+
+        // Weighted median, both halves keep at least one color.
+        let half = colors.iter().map(|(_, n)| *n as u64).sum::<u64>() / 2;
+        let mut seen = 0;
+        let mut split = colors.len() - 1;
+        for (at, (_, n)) in colors.iter().enumerate() {
+            seen += *n as u64;
+            if seen >= half {
+                split = (at + 1).min(colors.len() - 1);
+                break;
+            }
+        }
+
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|b| {
+            let total = b.iter().map(|(_, n)| *n as u64).sum::<u64>();
+            let mut color = [0u8; 4];
+            for (c, value) in color.iter_mut().enumerate() {
+                let sum = b.iter().map(|(p, n)| p[c] as u64 * *n as u64).sum::<u64>();
+                *value = ((sum + total / 2) / total) as u8;
+            }
+            color
+        })
+        .collect()
+}
+
+fn widest_channel(colors: &[([u8; 4], u32)]) -> (usize, u8) {
+    (0..4)
+        .map(|c| {
+            let min = colors.iter().map(|(p, _)| p[c]).min().unwrap_or(0);
+            let max = colors.iter().map(|(p, _)| p[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+/// Nearest palette color, cached as images repeat the same colors a lot.
+struct Nearest<'a> {
+    palette: &'a [[u8; 4]],
+    cache: HashMap<[u8; 4], u8>,
+}
+
+impl<'a> Nearest<'a> {
+    fn new(palette: &'a [[u8; 4]]) -> Self {
+        Self {
+            palette,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn index(&mut self, color: [u8; 4]) -> u8 {
+        let palette = self.palette;
+        *self.cache.entry(color).or_insert_with(|| {
+            let distance = |c: &[u8; 4]| -> u32 {
+                c.iter()
+                    .zip(color.iter())
+                    .map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+                    .sum()
+            };
+
+            palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, c)| distance(c))
+                .map_or(0, |(i, _)| i as u8)
+        })
+    }
+}
+
+/// Floyd–Steinberg error diffusion, left to right on every row.
+fn dither_indices(rgba: &[u8], width: usize, palette: &[[u8; 4]]) -> Vec<u8> {
+    let mut nearest = Nearest::new(palette);
+    let mut indices = Vec::with_capacity(rgba.len() / 4);
+
+    // Error carried to the current and the next row, 4 channels per pixel.
+    let mut current = vec![0f32; width * 4 + 8];
+    let mut next = vec![0f32; width * 4 + 8];
+
+    for row in rgba.chunks(width * 4) {
+        for (x, pixel) in row.chunks_exact(4).enumerate() {
+            // Offset by one pixel so x - 1 is never negative.
+            let at = (x + 1) * 4;
+
+            let mut wanted = [0u8; 4];
+            for c in 0..4 {
+                wanted[c] = (pixel[c] as f32 + current[at + c])
+                    .round()
+                    .clamp(0.0, 255.0) as u8;
+            }
+
+            let index = nearest.index(wanted);
+            indices.push(index);
+
+            let got = palette[index as usize];
+            for c in 0..4 {
+                let error = wanted[c] as f32 - got[c] as f32;
+                current[at + 4 + c] += error * 7.0 / 16.0;
+                next[at - 4 + c] += error * 3.0 / 16.0;
+                next[at + c] += error * 5.0 / 16.0;
+                next[at + 4 + c] += error / 16.0;
+            }
+        }
+
+        std::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|e| *e = 0.0);
+    }
+
+    indices
+}
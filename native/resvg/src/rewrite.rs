@@ -0,0 +1,205 @@
+// Changes made to the svg source before usvg conversion.
+
+use crate::dom::{self, Element, Node};
+use std::collections::{HashMap, HashSet};
+use usvg::roxmltree;
+
+#[derive(Clone, Default)]
+pub struct Rewrite {
+    /// The color `currentColor` resolves to when the svg doesn't set one.
+    pub current_color: Option<String>,
+    /// Values of `var(--name)`, keyed by name without the leading dashes.
+    pub variables: HashMap<String, String>,
+    /// Elements removed with their children.
+    pub hide_ids: Vec<String>,
+    /// Graphics kept with their ancestors and children, the other ones are
+    /// removed. Resources like gradients, `defs` or `style` are always kept.
+    pub show_only_ids: Option<Vec<String>>,
+    /// Content of `text`, `tspan` and `textPath` elements by id.
+    pub text: HashMap<String, String>,
+    /// Attribute changes, applied in order before everything else.
+    pub patch: Vec<Patch>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Patch {
+    /// An element id or a CSS selector.
+    pub target: String,
+    pub attribute: String,
+    /// `None` removes the attribute.
+    pub value: Option<String>,
+}
+
+/// Elements rendered where they are, as opposed to resources rendered only
+/// when referenced.
+const GRAPHICS: &[&str] = &[
+    "a", "circle", "ellipse", "g", "image", "line", "path", "polygon", "polyline", "rect", "svg",
+    "switch", "text", "use",
+];
+
+impl Rewrite {
+    pub fn is_empty(&self) -> bool {
+        self.current_color.is_none()
+            && self.variables.is_empty()
+            && self.hide_ids.is_empty()
+            && self.show_only_ids.is_none()
+            && self.text.is_empty()
+            && self.patch.is_empty()
+    }
+
+    /// The rewritten svg, `None` when there is nothing to change.
+    pub fn apply(&self, document: &roxmltree::Document) -> Result<Option<String>, String> {
+        if self.is_empty() || !document.root_element().has_tag_name((dom::SVG_NS, "svg")) {
+            return Ok(None);
+        }
+
+        let mut root = Element::from_xmltree(document);
+
+        for patch in &self.patch {
+            for path in root.select(&patch.target)? {
+                let element = root.descendant_mut(&path);
+                match &patch.value {
+                    Some(value) => element.set_attribute(&patch.attribute, value),
+                    None => element.remove_attribute(&patch.attribute),
+                }
+            }
+        }
+
+        if let Some(color) = &self.current_color {
+            if root.attribute("color").is_none() {
+                root.set_attribute("color", color);
+            }
+        }
+
+        if !self.variables.is_empty() {
+            root.visit_mut(&mut |element| {
+                for attribute in &mut element.attributes {
+                    if let Some(value) = substitute(&attribute.value, &self.variables) {
+                        attribute.value = value;
+                    }
+                }
+
+                if element.name == "style" {
+                    for child in &mut element.children {
+                        if let Node::Text(text) = child {
+                            if let Some(value) = substitute(text, &self.variables) {
+                                *text = value;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        if let Some(ids) = &self.show_only_ids {
+            let ids: HashSet<&str> = ids.iter().map(|id| id.as_str()).collect();
+            show_only(&mut root, &ids);
+        }
+
+        if !self.hide_ids.is_empty() {
+            let ids: HashSet<&str> = self.hide_ids.iter().map(|id| id.as_str()).collect();
+            root.visit_mut(&mut |element| {
+                element.children.retain(|child| match child {
+                    Node::Element(child) => !child.id().is_some_and(|id| ids.contains(id)),
+                    Node::Text(_) => true,
+                });
+            });
+        }
+
+        if !self.text.is_empty() {
+            root.visit_mut(&mut |element| {
+                if !matches!(element.name.as_str(), "text" | "tspan" | "textPath") {
+                    return;
+                }
+
+                // Child `tspan` elements are replaced too, with their positions.
+                if let Some(text) = element.id().and_then(|id| self.text.get(id)) {
+                    element.children = vec![Node::Text(text.clone())];
+                }
+            });
+        }
+
+        Ok(Some(root.to_xml()))
+    }
+}
+
+/// Removes the graphics of `element` which are not listed in `ids` and have
+/// no listed descendant. Whether any listed element was found is returned.
+fn show_only(element: &mut Element, ids: &HashSet<&str>) -> bool {
+    let mut found = false;
+    element.children.retain_mut(|child| match child {
+        Node::Element(child) if GRAPHICS.contains(&child.name.as_str()) => {
+            let keep = child.id().is_some_and(|id| ids.contains(id)) || show_only(child, ids);
+            found |= keep;
+            keep
+        }
+        _ => true,
+    });
+    found
+}
+
+/// Replaces `var(--name)` and `var(--name, fallback)` in `value`. Unknown
+/// names without a fallback are left as is. `None` when nothing changed.
+fn substitute(value: &str, variables: &HashMap<String, String>) -> Option<String> {
+    if !value.contains("var(") {
+        return None;
+    }
+
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut changed = false;
+
+    while let Some(start) = rest.find("var(") {
+        result.push_str(&rest[..start]);
+        let args = &rest[start + 4..];
+
+        // The fallback may contain parentheses itself, like `rgb(0, 0, 0)`.
+        let mut depth = 0;
+        let end = args.char_indices().find_map(|(i, c)| match c {
+            '(' => {
+                depth += 1;
+                None
+            }
+            ')' if depth == 0 => Some(i),
+            ')' => {
+                depth -= 1;
+                None
+            }
+            _ => None,
+        });
+
+        let end = match end {
+            Some(end) => end,
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+                break;
+            }
+        };
+
+        let (name, fallback) = match args[..end].split_once(',') {
+            Some((name, fallback)) => (name.trim(), Some(fallback.trim())),
+            None => (args[..end].trim(), None),
+        };
+
+        let replacement = name
+            .strip_prefix("--")
+            .and_then(|name| variables.get(name))
+            .map(|v| v.to_string())
+            .or_else(|| {
+                fallback.map(|f| substitute(f, variables).unwrap_or_else(|| f.to_string()))
+            });
+
+        match replacement {
+            Some(replacement) => {
+                result.push_str(&replacement);
+                changed = true;
+            }
+            None => result.push_str(&rest[start..start + 4 + end + 1]),
+        }
+        rest = &args[end + 1..];
+    }
+
+    result.push_str(rest);
+    changed.then_some(result)
+}
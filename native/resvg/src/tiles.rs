@@ -0,0 +1,102 @@
+// Tile layouts splitting a large render into small pngs.
+
+use std::path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layout {
+    /// Tiles of the full size render, the last row and column are cropped.
+    Grid,
+    /// Zoom levels halving the size down to a single tile, as used by web
+    /// maps. Tiles are always `tile_size` square.
+    Xyz,
+}
+
+/// A tile of the zoom level `z`, `x` and `y` are the column and the row.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tile {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Scale of the zoom level relative to the full size render.
+    pub scale: f32,
+}
+
+impl Tile {
+    /// Path of the tile image, `x_y.png` for a grid and `z/x/y.png` otherwise
+    /// when `extension` is `png`.
+    pub fn path(&self, layout: Layout, dir: &path::Path, extension: &str) -> path::PathBuf {
+        match layout {
+            Layout::Grid => dir.join(format!("{}_{}.{}", self.x, self.y, extension)),
+            Layout::Xyz => dir
+                .join(self.z.to_string())
+                .join(self.x.to_string())
+                .join(format!("{}.{}", self.y, extension)),
+        }
+    }
+}
+
+/// Lists the tiles covering a `width` x `height` render.
+pub fn tiles(layout: Layout, width: u32, height: u32, tile_size: u32) -> Vec<Tile> {
+    match layout {
+        Layout::Grid => level_tiles(0, 1.0, width, height, tile_size, true),
+        Layout::Xyz => {
+            let max_zoom = max_zoom(width.max(height), tile_size);
+
+            (0..=max_zoom)
+                .flat_map(|z| {
+                    let scale = 1.0 / (1u64 << (max_zoom - z)) as f32;
+                    let level_width = (width as f32 * scale).ceil() as u32;
+                    let level_height = (height as f32 * scale).ceil() as u32;
+                    level_tiles(z, scale, level_width, level_height, tile_size, false)
+                })
+                .collect()
+        }
+    }
+}
+
+/// Smallest zoom level at which the render fits in a single tile.
+fn max_zoom(size: u32, tile_size: u32) -> u32 {
+    let mut zoom = 0;
+    while (tile_size as u64) << zoom < size as u64 {
+        zoom += 1;
+    }
+    zoom
+}
+
+fn level_tiles(
+    z: u32,
+    scale: f32,
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    crop: bool,
+) -> Vec<Tile> {
+    let columns = width.div_ceil(tile_size);
+    let rows = height.div_ceil(tile_size);
+
+    let mut tiles = Vec::with_capacity(columns as usize * rows as usize);
+    for x in 0..columns {
+        for y in 0..rows {
+            let (tile_width, tile_height) = if crop {
+                (
+                    tile_size.min(width - x * tile_size),
+                    tile_size.min(height - y * tile_size),
+                )
+            } else {
+                (tile_size, tile_size)
+            };
+
+            tiles.push(Tile {
+                z,
+                x,
+                y,
+                width: tile_width,
+                height: tile_height,
+                scale,
+            });
+        }
+    }
+    tiles
+}
@@ -17,6 +17,42 @@ defmodule Resvg.Test do
     Path.join(font_dir(), name)
   end
 
+  defp png_size(buffer) do
+    <<0x89, "PNG", _::binary-size(12), width::32, height::32, _::binary>> =
+      :erlang.list_to_binary(buffer)
+
+    {width, height}
+  end
+
+  defp png_color_type(buffer) do
+    <<0x89, "PNG", _::binary-size(20), depth, color_type, _::binary>> =
+      :erlang.list_to_binary(buffer)
+
+    {depth, color_type}
+  end
+
+  defp render_without(svg_string, ids) do
+    svg_string =
+      Enum.reduce(ids, svg_string, fn id, svg ->
+        String.replace(svg, ~r/<rect id="#{id}"[^>]*>/, "")
+      end)
+
+    {:ok, buffer} = Resvg.svg_string_to_png_buffer(svg_string)
+    buffer
+  end
+
+  # Buffer of a `width` by `height` svg with a single red rect.
+  defp red_rect_buffer(width, height, {x, y, rect_width, rect_height}) do
+    {:ok, buffer} =
+      Resvg.svg_string_to_png_buffer("""
+        <svg xmlns="http://www.w3.org/2000/svg" width="#{width}" height="#{height}">
+          <rect x="#{x}" y="#{y}" width="#{rect_width}" height="#{rect_height}" fill="red" />
+        </svg>
+      """)
+
+    buffer
+  end
+
   describe "svg_to_png/3" do
     test "success convert rustacean.svg to a png image" do
       input = image_path("rustacean.svg")
@@ -135,6 +171,45 @@ defmodule Resvg.Test do
         reviewed: true
       )
     end
+
+    test "render svg with image tag resolve from preloaded images" do
+      output = image_path("snapshots/image-test-preloaded.png")
+      reference = image_path("image-test-reference.png")
+
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" width="200" height="200"
+             xmlns:xlink="http://www.w3.org/1999/xlink"
+        >
+          <image xlink:href="avatar.png" width="200" height="200" />
+        </svg>
+      """
+
+      images = %{"avatar.png" => File.read!(image_path("mdn-logo.png"))}
+
+      assert :ok =
+               Resvg.svg_string_to_png(svg_string, output, resources_dir: @tmp, images: images)
+
+      approve(
+        snapshot: output,
+        reference: reference,
+        reviewed: true
+      )
+    end
+
+    test "fail relative image path without resources_dir" do
+      output = image_path("image-test-bug.png")
+
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" width="200" height="200"
+             xmlns:xlink="http://www.w3.org/1999/xlink"
+        >
+          <image xlink:href="mdn-logo.png" width="200" height="200" />
+        </svg>
+      """
+
+      assert {:error, msg} = Resvg.svg_string_to_png(svg_string, output)
+      assert msg == "Make sure to set resources_dir to resolve the relative path 'mdn-logo.png'"
+    end
   end
 
   describe "svg_string_to_png_buffer/3" do
@@ -158,6 +233,832 @@ defmodule Resvg.Test do
         reviewed: true
       )
     end
+
+    test "success without resources_dir" do
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>
+      """
+
+      assert {:ok, buffer} = Resvg.svg_string_to_png_buffer(svg_string)
+      assert [0x89, ?P, ?N, ?G | _] = buffer
+    end
+  end
+
+  describe "fit and align" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+        <rect width="200" height="100" fill="red" />
+      </svg>
+    """
+
+    test "keep the aspect ratio by default" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, width: 100, height: 100)
+      assert png_size(buffer) == {100, 50}
+    end
+
+    test "output the exact size" do
+      for fit <- [:contain, :cover, :fill] do
+        {:ok, buffer} =
+          Resvg.svg_string_to_png_buffer(@svg_string, width: 100, height: 100, fit: fit)
+
+        assert png_size(buffer) == {100, 100}
+      end
+    end
+
+    test "align the letterboxed image" do
+      opts = [width: 100, height: 100, fit: :contain]
+
+      {:ok, top} = Resvg.svg_string_to_png_buffer(@svg_string, opts ++ [align: :top])
+      assert top == red_rect_buffer(100, 100, {0, 0, 100, 50})
+
+      {:ok, center} = Resvg.svg_string_to_png_buffer(@svg_string, opts)
+      assert center == red_rect_buffer(100, 100, {0, 25, 100, 50})
+
+      {:ok, bottom} = Resvg.svg_string_to_png_buffer(@svg_string, opts ++ [align: :bottom])
+      assert bottom == red_rect_buffer(100, 100, {0, 50, 100, 50})
+    end
+
+    test "crop the covering image" do
+      # Red in the left half only.
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+          <rect width="100" height="100" fill="red" />
+        </svg>
+      """
+
+      opts = [width: 100, height: 100, fit: :cover]
+
+      {:ok, left} = Resvg.svg_string_to_png_buffer(svg_string, opts ++ [align: :left])
+      assert left == red_rect_buffer(100, 100, {0, 0, 100, 100})
+
+      {:ok, center} = Resvg.svg_string_to_png_buffer(svg_string, opts)
+      assert center == red_rect_buffer(100, 100, {0, 0, 50, 100})
+    end
+  end
+
+  describe "max and min dimensions" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+        <rect width="200" height="100" fill="red" />
+      </svg>
+    """
+
+    test "scale down larger images" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, max_width: 100)
+      assert png_size(buffer) == {100, 50}
+
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, max_height: 25)
+      assert png_size(buffer) == {50, 25}
+    end
+
+    test "never upscale smaller images" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, max_width: 1024)
+      assert png_size(buffer) == {200, 100}
+    end
+
+    test "scale up smaller images" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, min_width: 400)
+      assert png_size(buffer) == {400, 200}
+
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string, min_width: 400, max_height: 150)
+
+      assert png_size(buffer) == {300, 150}
+    end
+  end
+
+  describe "physical units" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+        <rect width="200" height="100" fill="red" />
+      </svg>
+    """
+
+    test "convert the size with the dpi" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, width: {210, :mm}, dpi: 300)
+      assert png_size(buffer) == {2480, 1240}
+
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, width: {1, :in})
+      assert png_size(buffer) == {96, 48}
+
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string, width: {2.54, :cm}, height: {36, :pt})
+
+      assert png_size(buffer) == {96, 48}
+    end
+
+    test "fail sizes rounding to zero pixels" do
+      assert {:error, "target size is zero"} =
+               Resvg.svg_string_to_png_buffer(@svg_string, width: {0.1, :mm})
+
+      assert {:error, "target size is zero"} =
+               Resvg.svg_string_to_png_buffer(@svg_string, height: {-1, :in})
+    end
+  end
+
+  describe "png metadata" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect width="20" height="10" fill="red" />
+      </svg>
+    """
+
+    test "write the dpi as physical resolution" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, dpi: 300)
+      png = :erlang.list_to_binary(buffer)
+
+      assert :binary.match(png, <<"pHYs", 11811::32, 11811::32, 1>>) != :nomatch
+    end
+
+    test "write text chunks" do
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string,
+          png_metadata: [{"Source", "app"}, title: "Logo", creation_time: "2024"]
+        )
+
+      png = :erlang.list_to_binary(buffer)
+      assert :binary.match(png, <<"tEXtTitle", 0, "Logo">>) != :nomatch
+      assert :binary.match(png, <<"tEXtCreation Time", 0, "2024">>) != :nomatch
+      assert :binary.match(png, <<"tEXtSource", 0, "app">>) != :nomatch
+    end
+  end
+
+  describe "png encoding" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+        <rect width="200" height="100" fill="red" />
+        <rect width="100" height="100" fill="blue" />
+      </svg>
+    """
+
+    test "write RGBA by default" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string)
+      assert png_color_type(buffer) == {8, 6}
+    end
+
+    test "reduce to a palette" do
+      {:ok, rgba} = Resvg.svg_string_to_png_buffer(@svg_string)
+
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string,
+          png: [reduce: true, compression: :best, filter: :adaptive]
+        )
+
+      assert png_color_type(buffer) == {1, 3}
+      assert length(buffer) < length(rgba)
+    end
+  end
+
+  describe "palette" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="120" height="60">
+        <defs>
+          <linearGradient id="gradient">
+            <stop offset="0" stop-color="red" />
+            <stop offset="1" stop-color="blue" />
+          </linearGradient>
+        </defs>
+        <rect width="120" height="60" fill="url(#gradient)" />
+      </svg>
+    """
+
+    test "quantize to an indexed png" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, palette: [colors: 16])
+      assert png_color_type(buffer) == {4, 3}
+      assert png_size(buffer) == {120, 60}
+
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string, palette: [colors: 4, dither: true])
+
+      assert png_color_type(buffer) == {2, 3}
+    end
+
+    test "quantize to a gif" do
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string, palette: [colors: 8, format: :gif])
+
+      assert <<"GIF89a", 120::little-16, 60::little-16, _::binary>> =
+               :erlang.list_to_binary(buffer)
+    end
+  end
+
+  describe "monochrome" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="10" height="2">
+        <rect width="5" height="2" fill="black" />
+      </svg>
+    """
+
+    test "write a 1-bit png" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, monochrome: [])
+      assert png_color_type(buffer) == {1, 0}
+    end
+
+    test "write packed bits" do
+      assert {:ok, [0xF8, 0x00, 0xF8, 0x00]} =
+               Resvg.svg_string_to_png_buffer(@svg_string, monochrome: [format: :bits])
+
+      assert {:ok, pbm} = Resvg.svg_string_to_png_buffer(@svg_string, monochrome: [format: :pbm])
+      assert :erlang.list_to_binary(pbm) == <<"P4\n10 2\n", 0xF8, 0x00, 0xF8, 0x00>>
+    end
+
+    test "write printer commands" do
+      assert {:ok, escpos} =
+               Resvg.svg_string_to_png_buffer(@svg_string, monochrome: [format: :escpos])
+
+      assert escpos == [0x1D, 0x76, 0x30, 0, 2, 0, 2, 0, 0xF8, 0x00, 0xF8, 0x00]
+
+      assert {:ok, zpl} = Resvg.svg_string_to_png_buffer(@svg_string, monochrome: [format: :zpl])
+      assert to_string(zpl) == "^XA^FO0,0^GFA,4,4,2,F800F800^FS^XZ"
+    end
+  end
+
+  describe "color transform" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect width="20" height="10" fill="red" />
+      </svg>
+    """
+
+    test "apply named transforms" do
+      # Red through each matrix, rounded to 8 bits.
+      colors = [grayscale: "rgb(54, 54, 54)", sepia: "rgb(100, 89, 69)", invert: "cyan"]
+
+      for {transform, color} <- colors do
+        expected_svg = String.replace(@svg_string, "red", color)
+        {:ok, expected} = Resvg.svg_string_to_png_buffer(expected_svg)
+        {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, color_transform: transform)
+        assert buffer == expected
+      end
+    end
+
+    test "apply a matrix" do
+      identity = [1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0]
+
+      {:ok, original} = Resvg.svg_string_to_png_buffer(@svg_string)
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, color_transform: identity)
+
+      assert buffer == original
+
+      half_alpha = [1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0.5, 0]
+
+      expected_svg = String.replace(@svg_string, "red", "#ff000080")
+      {:ok, expected} = Resvg.svg_string_to_png_buffer(expected_svg)
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, color_transform: half_alpha)
+
+      assert buffer == expected
+    end
+  end
+
+  describe "stylesheet" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect class="primary" width="20" height="10" fill="green" />
+      </svg>
+    """
+
+    test "override presentation attributes" do
+      red = String.replace(@svg_string, "green", "red")
+      {:ok, expected} = Resvg.svg_string_to_png_buffer(red)
+
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string, stylesheet: ".primary { fill: #f00 }")
+
+      assert buffer == expected
+    end
+  end
+
+  describe "current color and variables" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect width="10" height="10" fill="currentColor" />
+        <rect x="10" width="10" height="10" style="fill: var(--accent, blue)" />
+      </svg>
+    """
+
+    @expected """
+      <svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <rect width="10" height="10" fill="red" />
+        <rect x="10" width="10" height="10" fill="lime" />
+      </svg>
+    """
+
+    test "resolve currentColor and var()" do
+      {:ok, expected} = Resvg.svg_string_to_png_buffer(@expected)
+
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string,
+          current_color: "red",
+          variables: %{accent: "lime"}
+        )
+
+      assert buffer == expected
+    end
+
+    test "use fallback of unknown variables" do
+      blue = String.replace(@expected, "lime", "blue")
+      {:ok, expected} = Resvg.svg_string_to_png_buffer(blue)
+
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string,
+          current_color: "red",
+          variables: %{"--other" => "lime"}
+        )
+
+      assert buffer == expected
+    end
+
+    test "fail current color" do
+      assert {:error, "Error current_color: invalid value"} =
+               Resvg.svg_string_to_png_buffer(@svg_string, current_color: "bug")
+    end
+  end
+
+  describe "visibility by id" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="30" height="10">
+        <rect id="base" width="10" height="10" fill="blue" />
+        <g id="overlays">
+          <rect id="grid" x="10" width="10" height="10" fill="red" />
+          <rect id="notes" x="20" width="10" height="10" fill="lime" />
+        </g>
+      </svg>
+    """
+
+    test "hide ids" do
+      assert {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, hide_ids: ["grid"])
+      assert buffer == render_without(@svg_string, ["grid"])
+
+      assert {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, hide_ids: ["overlays"])
+      assert buffer == render_without(@svg_string, ["grid", "notes"])
+    end
+
+    test "show only ids" do
+      assert {:ok, buffer} =
+               Resvg.svg_string_to_png_buffer(@svg_string, show_only_ids: ["base", "notes"])
+
+      assert buffer == render_without(@svg_string, ["grid"])
+
+      assert {:ok, buffer} =
+               Resvg.svg_string_to_png_buffer(@svg_string,
+                 show_only_ids: ["overlays"],
+                 hide_ids: ["notes"]
+               )
+
+      assert buffer == render_without(@svg_string, ["base", "notes"])
+    end
+  end
+
+  describe "text replacement" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="40">
+        <text id="name" x="5" y="30" font-family="Roboto" font-size="20">{{name}}</text>
+      </svg>
+    """
+
+    test "replace text content by id" do
+      opts = [skip_system_fonts: true, font_dirs: [font_dir()], resources_dir: @tmp]
+
+      escaped = String.replace(@svg_string, "{{name}}", "Tom &amp; Jerry &lt;3")
+      {:ok, expected} = Resvg.svg_string_to_png_buffer(escaped, opts)
+
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string, [text: %{name: "Tom & Jerry <3"}] ++ opts)
+
+      assert buffer == expected
+    end
+  end
+
+  describe "patch" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <g class="bars">
+          <rect id="bar-1" y="5" width="10" height="5" fill="red" />
+          <rect id="bar-2" x="10" y="5" width="10" height="5" fill="red" stroke="black" />
+        </g>
+      </svg>
+    """
+
+    @expected """
+      <svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <g class="bars">
+          <rect id="bar-1" y="0" width="10" height="10" fill="blue" />
+          <rect id="bar-2" x="10" y="5" width="10" height="5" fill="blue" />
+        </g>
+      </svg>
+    """
+
+    test "patch attributes by id and selector" do
+      {:ok, expected} = Resvg.svg_string_to_png_buffer(@expected)
+
+      patch = [
+        {"bar-1", :height, 10},
+        {"bar-1", :y, 0},
+        {".bars rect", :fill, "blue"},
+        {"#bar-2", :stroke, nil}
+      ]
+
+      assert {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, patch: patch)
+      assert buffer == expected
+    end
+
+    test "fail invalid selector" do
+      assert {:error, "Error patch: invalid selector '[['"} =
+               Resvg.svg_string_to_png_buffer(@svg_string, patch: [{"[[", :fill, "blue"}])
+    end
+  end
+
+  describe "padding" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+        <rect width="200" height="100" fill="red" />
+      </svg>
+    """
+
+    test "enlarge the image on all sides" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, padding: 10)
+      assert png_size(buffer) == {220, 120}
+
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, padding: {10, :percent})
+      assert png_size(buffer) == {240, 120}
+    end
+
+    test "accept a padding per side" do
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string, padding: {10, 0, {10, :percent}, 5})
+
+      assert png_size(buffer) == {205, 120}
+    end
+
+    test "apply after the size options" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, width: 100, padding: 8)
+      assert png_size(buffer) == {116, 66}
+    end
+  end
+
+  describe "rotate and flip" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+        <rect width="100" height="100" fill="red" />
+        <rect x="100" width="100" height="100" fill="blue" />
+      </svg>
+    """
+
+    test "rotate by right angles" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, rotate: 90)
+      assert png_size(buffer) == {100, 200}
+
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, rotate: 180)
+      assert png_size(buffer) == {200, 100}
+
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, rotate: -90)
+      assert png_size(buffer) == {100, 200}
+    end
+
+    test "expand the canvas for arbitrary angles" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, rotate: 45)
+      assert png_size(buffer) == {213, 213}
+    end
+
+    # Red in the top left corner only.
+    @corner_svg """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+        <rect width="100" height="50" fill="red" />
+      </svg>
+    """
+
+    test "flip the image" do
+      {:ok, horizontal} = Resvg.svg_string_to_png_buffer(@corner_svg, flip: :horizontal)
+      assert horizontal == red_rect_buffer(200, 100, {100, 0, 100, 50})
+
+      {:ok, vertical} = Resvg.svg_string_to_png_buffer(@corner_svg, flip: :vertical)
+      assert vertical == red_rect_buffer(200, 100, {0, 50, 100, 50})
+    end
+
+    test "flip before rotating" do
+      # Flipped to the top right corner, then rotated to the bottom right one.
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@corner_svg, flip: :horizontal, rotate: 90)
+
+      assert buffer == red_rect_buffer(100, 200, {50, 100, 50, 100})
+    end
+  end
+
+  describe "region" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+        <rect width="100" height="100" fill="red" />
+        <rect x="100" width="100" height="100" fill="blue" />
+      </svg>
+    """
+
+    test "render only the region" do
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, region: {100, 0, 100, 100})
+      assert png_size(buffer) == {100, 100}
+
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(@svg_string, region: {110, 0, 80, 80})
+      assert png_size(buffer) == {80, 80}
+    end
+
+    test "scale the region to the output size" do
+      {:ok, buffer} =
+        Resvg.svg_string_to_png_buffer(@svg_string, region: {50, 25, 100, 50}, width: 400)
+
+      assert png_size(buffer) == {400, 200}
+    end
+
+    test "use the viewBox units" do
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" width="100" height="100" viewBox="0 0 1000 1000">
+          <rect width="1000" height="1000" fill="blue" />
+          <rect x="500" y="500" width="500" height="500" fill="red" />
+        </svg>
+      """
+
+      expected = """
+        <svg xmlns="http://www.w3.org/2000/svg" width="50" height="50">
+          <rect width="50" height="50" fill="red" />
+        </svg>
+      """
+
+      {:ok, expected} = Resvg.svg_string_to_png_buffer(expected)
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(svg_string, region: {500, 500, 500, 500})
+
+      assert buffer == expected
+    end
+  end
+
+  describe "render_tiles/3" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="250" height="100">
+        <rect width="250" height="100" fill="red" />
+      </svg>
+    """
+
+    test "split the render in a grid" do
+      assert {:ok, tiles} = Resvg.render_tiles(@svg_string, 100)
+
+      assert Enum.map(tiles, fn {x, y, buffer} -> {x, y, png_size(buffer)} end) == [
+               {0, 0, {100, 100}},
+               {1, 0, {100, 100}},
+               {2, 0, {50, 100}}
+             ]
+    end
+
+    test "split the render in a zoom pyramid" do
+      assert {:ok, tiles} = Resvg.render_tiles(@svg_string, 100, layout: :xyz)
+
+      assert Enum.map(tiles, fn {z, x, y, _buffer} -> {z, x, y} end) == [
+               {0, 0, 0},
+               {1, 0, 0},
+               {1, 1, 0},
+               {2, 0, 0},
+               {2, 1, 0},
+               {2, 2, 0}
+             ]
+
+      assert Enum.all?(tiles, fn {_z, _x, _y, buffer} -> png_size(buffer) == {100, 100} end)
+    end
+
+    test "render filters reaching over tiles" do
+      # The shadow is moved away from the rect, to the last tile.
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" width="256" height="64">
+          <filter id="shadow" filterUnits="userSpaceOnUse" x="0" y="0" width="256" height="64">
+            <feGaussianBlur stdDeviation="2" />
+            <feOffset dx="192" />
+          </filter>
+          <rect x="8" y="8" width="48" height="48" filter="url(#shadow)" />
+        </svg>
+      """
+
+      # Raw rows, 8 bytes wide for a tile.
+      opts = [monochrome: [format: :bits]]
+      {:ok, render} = Resvg.svg_string_to_png_buffer(svg_string, opts)
+      {:ok, tiles} = Resvg.render_tiles(svg_string, 64, opts)
+
+      stitched =
+        tiles
+        |> Enum.map(fn {_x, _y, bits} -> Enum.chunk_every(bits, 8) end)
+        |> Enum.zip_with(&Enum.concat/1)
+        |> Enum.concat()
+
+      assert stitched == render
+    end
+
+    @tag :tmp_dir
+    test "write the tiles to a directory", %{tmp_dir: tmp_dir} do
+      assert :ok = Resvg.render_tiles(@svg_string, 100, layout: :xyz, output_dir: tmp_dir)
+
+      assert File.exists?(Path.join(tmp_dir, "0/0/0.png"))
+      assert File.exists?(Path.join(tmp_dir, "2/2/0.png"))
+    end
+
+    @tag :tmp_dir
+    test "name the tile files after the output format", %{tmp_dir: tmp_dir} do
+      options = [palette: [format: :gif], output_dir: tmp_dir]
+      assert :ok = Resvg.render_tiles(@svg_string, 100, options)
+      assert <<"GIF89a", _::binary>> = File.read!(Path.join(tmp_dir, "0_0.gif"))
+
+      options = [monochrome: [format: :pbm], output_dir: tmp_dir]
+      assert :ok = Resvg.render_tiles(@svg_string, 100, options)
+      assert <<"P4", _::binary>> = File.read!(Path.join(tmp_dir, "2_0.pbm"))
+    end
+  end
+
+  describe "render_async/3" do
+    test "send the png buffer to the caller" do
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>
+      """
+
+      {:ok, expected} = Resvg.svg_string_to_png_buffer(svg_string)
+
+      ref = Resvg.render_async(svg_string)
+      assert is_reference(ref)
+      assert_receive {^ref, {:ok, ^expected}}, 5_000
+    end
+
+    test "send the error to the given pid" do
+      parent = self()
+
+      pid =
+        spawn(fn ->
+          receive do
+            message -> send(parent, {:forward, message})
+          end
+        end)
+
+      ref = Resvg.render_async("<svg", [], pid)
+      assert_receive {:forward, {^ref, {:error, _reason}}}, 5_000
+    end
+
+    test "send an error for bad options" do
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>
+      """
+
+      ref = Resvg.render_async(svg_string, width: 0)
+      assert_receive {^ref, {:error, "target size is zero"}}, 5_000
+
+      ref = Resvg.render_async(svg_string, width: {0.1, :mm})
+      assert_receive {^ref, {:error, "target size is zero"}}, 5_000
+    end
+  end
+
+  describe "render_sizes/3" do
+    test "render every size in order" do
+      input = image_path("rustacean.svg")
+      svg_string = File.read!(input)
+
+      sizes = [120, {120, 80}, {:height, 80}, {:zoom, 2.0}]
+
+      assert {:ok, buffers} = Resvg.render_sizes(svg_string, sizes)
+
+      expected =
+        Enum.map([[width: 120], [width: 120, height: 80], [height: 80], [zoom: 2.0]], fn opts ->
+          {:ok, buffer} = Resvg.svg_string_to_png_buffer(svg_string, opts)
+          buffer
+        end)
+
+      assert buffers == expected
+    end
+
+    test "fail invalid svg" do
+      assert {:error, _reason} = Resvg.render_sizes("<svg", [16, 32])
+    end
+  end
+
+  describe "render_ico/3" do
+    test "pack every size" do
+      svg_string = File.read!(image_path("rustacean.svg"))
+
+      assert {:ok, buffer} = Resvg.render_ico(svg_string, [16, 32, 256])
+
+      assert <<0::little-16, 1::little-16, 3::little-16, entries::binary-size(48), _::binary>> =
+               :erlang.list_to_binary(buffer)
+
+      assert [16, 32, 0] = for(<<size, _::binary-size(15) <- entries>>, do: size)
+    end
+
+    test "fail size too large" do
+      svg_string = File.read!(image_path("rustacean.svg"))
+
+      assert {:error, "ico size must be between 1 and 256, got 512"} =
+               Resvg.render_ico(svg_string, [16, 512])
+    end
+
+    test "keep padded and rotated images in their size" do
+      svg_string = File.read!(image_path("rustacean.svg"))
+
+      for opts <- [[padding: 2], [rotate: 45], [max_width: 8]] do
+        assert {:ok, buffer} = Resvg.render_ico(svg_string, [16], opts)
+
+        # A single entry, its png follows the 22 bytes of header.
+        assert <<_header::binary-size(22), png::binary>> = :erlang.list_to_binary(buffer)
+        assert <<0x89, "PNG", _::binary-size(12), 16::32, 16::32, _::binary>> = png
+      end
+    end
+
+    test "write png images whatever the output format" do
+      svg_string = File.read!(image_path("rustacean.svg"))
+
+      for opts <- [[palette: [format: :gif]], [monochrome: [format: :zpl]]] do
+        assert {:ok, buffer} = Resvg.render_ico(svg_string, [16], opts)
+
+        assert <<_header::binary-size(22), 0x89, "PNG", _::binary>> =
+                 :erlang.list_to_binary(buffer)
+      end
+    end
+
+    test "fail sizes before parsing the svg" do
+      assert {:error, "ico size must be between 1 and 256, got 512"} =
+               Resvg.render_ico("not an svg", [512])
+
+      assert {:error, "ico must contain between 1 and 65535 sizes"} =
+               Resvg.render_ico("not an svg", [])
+    end
+  end
+
+  describe "render_icns/3" do
+    test "pack every size" do
+      svg_string = File.read!(image_path("rustacean.svg"))
+
+      assert {:ok, buffer} = Resvg.render_icns(svg_string, [16, 512])
+
+      buffer = :erlang.list_to_binary(buffer)
+      size = byte_size(buffer)
+
+      assert <<"icns", ^size::big-32, "icp4", len::big-32, rest::binary>> = buffer
+      assert <<_png::binary-size(len - 8), "ic09", _::binary>> = rest
+    end
+
+    test "fail unsupported size" do
+      svg_string = File.read!(image_path("rustacean.svg"))
+
+      assert {:error, _reason} = Resvg.render_icns(svg_string, [48])
+    end
+
+    test "fail sizes before parsing the svg" do
+      assert {:error, "icns must contain at least 1 size"} = Resvg.render_icns("not an svg", [])
+    end
+  end
+
+  describe "render_batch/2" do
+    test "render every item in order" do
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 20 20">
+          <rect width="100" height="100" />
+        </svg>
+      """
+
+      widths = [16, 32, 64, 128]
+      items = Enum.map(widths, &{svg_string, width: &1})
+
+      assert {:ok, results} = Resvg.render_batch(items, background: "white")
+
+      for {width, result} <- Enum.zip(widths, results) do
+        assert {:ok, expected} =
+                 Resvg.svg_string_to_png_buffer(svg_string, width: width, background: "white")
+
+        assert result == {:ok, expected}
+      end
+    end
+
+    test "share the batch fonts" do
+      input = image_path("text-font-change.svg")
+      reference = image_path("text-font-change-reference.png")
+      output = image_path("snapshots/text-font-change_render_batch.png")
+
+      svg_string = File.read!(input)
+
+      assert {:ok, [{:ok, image_data}, {:error, _reason}]} =
+               Resvg.render_batch([{svg_string, []}, {"<svg", []}],
+                 dpi: 256,
+                 skip_system_fonts: true,
+                 font_dirs: [font_dir()]
+               )
+
+      File.write!(output, image_data)
+
+      approve(
+        snapshot: output,
+        reference: reference,
+        reviewed: true
+      )
+    end
+
+    test "fail to load the batch fonts" do
+      roboto = font_file("Rototo/Rototo-Regular.ttf")
+
+      assert {:error, "Error loading font file: No such file or directory (os error 2)"} =
+               Resvg.render_batch([], skip_system_fonts: true, font_files: [roboto])
+    end
   end
 
   describe "revg deals correctly with <tspan> elements inside a <text> element" do
@@ -236,12 +1137,188 @@ defmodule Resvg.Test do
     end
   end
 
+  describe "limits" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+        <defs>
+          <filter id="blur"><feGaussianBlur stdDeviation="5" /></filter>
+        </defs>
+        <rect width="50" height="50" filter="url(#blur)" />
+        <rect x="50" y="50" width="50" height="50" />
+      </svg>
+    """
+
+    test "render within limits" do
+      limits = [
+        max_input_bytes: 1_000,
+        max_elements: 10,
+        max_pixels: 100 * 100,
+        max_filter_area: 100 * 100,
+        allow_dtd: false
+      ]
+
+      assert {:ok, _buffer} = Resvg.svg_string_to_png_buffer(@svg_string, limits: limits)
+    end
+
+    test "fail max_input_bytes" do
+      assert {:error, {:limit_exceeded, :max_input_bytes}} =
+               Resvg.svg_string_to_png_buffer(@svg_string, limits: [max_input_bytes: 100])
+    end
+
+    test "fail max_elements" do
+      assert {:error, {:limit_exceeded, :max_elements}} =
+               Resvg.svg_string_to_png_buffer(@svg_string, limits: [max_elements: 3])
+    end
+
+    test "fail max_elements once use elements are instantiated" do
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="10" height="10">
+          <rect id="a" width="1" height="1" />
+          <g id="b"><use xlink:href="#a" /><use xlink:href="#a" /><use xlink:href="#a" /></g>
+          <g id="c"><use xlink:href="#b" /><use xlink:href="#b" /><use xlink:href="#b" /></g>
+          <g><use xlink:href="#c" /><use xlink:href="#c" /><use xlink:href="#c" /></g>
+        </svg>
+      """
+
+      assert {:error, {:limit_exceeded, :max_elements}} =
+               Resvg.svg_string_to_png_buffer(svg_string, limits: [max_elements: 20])
+    end
+
+    test "fail max_pixels" do
+      assert {:error, {:limit_exceeded, :max_pixels}} =
+               Resvg.svg_string_to_png_buffer(@svg_string,
+                 width: 1000,
+                 limits: [max_pixels: 100 * 100]
+               )
+    end
+
+    test "fail max_filter_area" do
+      assert {:error, {:limit_exceeded, :max_filter_area}} =
+               Resvg.svg_string_to_png_buffer(@svg_string,
+                 zoom: 4.0,
+                 limits: [max_filter_area: 100 * 100]
+               )
+    end
+
+    test "fail allow_dtd" do
+      svg_string = """
+        <!DOCTYPE svg [<!ENTITY size "10">]>
+        <svg xmlns="http://www.w3.org/2000/svg" width="&size;" height="&size;" />
+      """
+
+      assert {:ok, _buffer} = Resvg.svg_string_to_png_buffer(svg_string)
+
+      assert {:error, {:limit_exceeded, :allow_dtd}} =
+               Resvg.svg_string_to_png_buffer(svg_string, limits: [allow_dtd: false])
+    end
+
+    test "fail limits of nested svg images" do
+      nested = """
+        <!DOCTYPE svg [<!ENTITY color "red">]>
+        <svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+          <rect width="10" height="10" fill="&color;" />
+        </svg>
+      """
+
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="10" height="10">
+          <image width="10" height="10" xlink:href="nested.svg" />
+        </svg>
+      """
+
+      images = %{"nested.svg" => nested}
+      assert {:ok, _buffer} = Resvg.svg_string_to_png_buffer(svg_string, images: images)
+
+      assert {:error, {:limit_exceeded, :allow_dtd}} =
+               Resvg.svg_string_to_png_buffer(svg_string,
+                 images: images,
+                 limits: [allow_dtd: false]
+               )
+
+      data_url = "data:image/svg+xml;base64," <> Base.encode64(nested)
+      svg_string = String.replace(svg_string, "nested.svg", data_url)
+
+      assert {:error, {:limit_exceeded, :allow_dtd}} =
+               Resvg.svg_string_to_png_buffer(svg_string, limits: [allow_dtd: false])
+    end
+
+    test "fail max_input_bytes while inflating svgz" do
+      svg = ~s(<svg xmlns="http://www.w3.org/2000/svg">#{String.duplicate(" ", 100_000)}</svg>)
+      input = Path.join(@tmp, "resvg-bomb.svgz")
+      File.write!(input, :zlib.gzip(svg))
+
+      assert {:error, {:limit_exceeded, :max_input_bytes}} =
+               Resvg.svg_to_png(input, Path.join(@tmp, "resvg-bomb.png"),
+                 limits: [max_input_bytes: 1_000]
+               )
+    end
+
+    test "count only elements for max_elements" do
+      rects = String.duplicate("<!-- a rect -->\n  <rect width=\"1\" height=\"1\" />\n", 10)
+      svg_string = ~s(<svg xmlns="http://www.w3.org/2000/svg">\n#{rects}</svg>)
+
+      assert {:ok, _buffer} =
+               Resvg.svg_string_to_png_buffer(svg_string, limits: [max_elements: 11])
+
+      assert {:error, {:limit_exceeded, :max_elements}} =
+               Resvg.svg_string_to_png_buffer(svg_string, limits: [max_elements: 10])
+    end
+
+    test "fail max_elements of a large document" do
+      groups = String.duplicate("<g/>", 10_000)
+      svg_string = ~s(<svg xmlns="http://www.w3.org/2000/svg">#{groups}</svg>)
+
+      assert {:error, {:limit_exceeded, :max_elements}} =
+               Resvg.svg_string_to_png_buffer(svg_string, limits: [max_elements: 10])
+    end
+  end
+
+  describe "timeout" do
+    @svg_string """
+      <svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+        <filter id="blur" x="-10" y="-10" width="20" height="20">
+          <feGaussianBlur stdDeviation="30" />
+        </filter>
+        <rect width="100" height="100" filter="url(#blur)" />
+      </svg>
+    """
+
+    test "render before the timeout" do
+      assert {:ok, _buffer} = Resvg.svg_string_to_png_buffer(@svg_string, timeout: 60_000)
+    end
+
+    test "fail timeout" do
+      assert {:error, :timeout} =
+               Resvg.svg_string_to_png_buffer(@svg_string, zoom: 30.0, timeout: 1)
+    end
+
+    test "render filters reaching over bands the same" do
+      svg_string = """
+        <svg xmlns="http://www.w3.org/2000/svg" width="200" height="4000">
+          <filter id="shadow" filterUnits="userSpaceOnUse" x="0" y="0" width="200" height="4000">
+            <feGaussianBlur stdDeviation="50" />
+            <feOffset dy="3000" />
+          </filter>
+          <rect x="50" y="100" width="100" height="300" fill="red" filter="url(#shadow)" />
+        </svg>
+      """
+
+      {:ok, buffer} = Resvg.svg_string_to_png_buffer(svg_string)
+      assert {:ok, ^buffer} = Resvg.svg_string_to_png_buffer(svg_string, timeout: 60_000)
+    end
+  end
+
   describe "list_fonts/1" do
     test "return fonts list" do
       {:ok, fonts} = Resvg.list_fonts(resources_dir: @tmp)
       assert is_list(fonts)
     end
 
+    test "without resources_dir" do
+      {:ok, fonts} = Resvg.list_fonts()
+      assert is_list(fonts)
+    end
+
     test "without system fonts" do
       {:ok, fonts} = Resvg.list_fonts(skip_system_fonts: true, resources_dir: @tmp)
       assert Enum.empty?(fonts)